@@ -1,3 +1,5 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use crate::throw_string;
 use crate::lang_core::interp::{
     LangResult,
@@ -10,6 +12,7 @@ use crate::lang_core::interp::{
     borrow_val
 };
 use crate::builtins::math::val_to_f64;
+use crate::builtins::string::pretty_string;
 
 pub fn test_equality(item1: &Gc<VarValues>, item2: &Gc<VarValues>) -> LangResult<bool> {
     use VarValues::*;
@@ -18,7 +21,9 @@ pub fn test_equality(item1: &Gc<VarValues>, item2: &Gc<VarValues>) -> LangResult
             Ok(true)
         },
         (Nil, Str(s)) |
-        (Str(s), Nil) |
+        (Str(s), Nil) => {
+            Ok(s.is_empty())
+        },
         (Nil, AstStr(s, _)) |
         (AstStr(s, _), Nil) => {
             Ok(s.is_empty())
@@ -29,9 +34,15 @@ pub fn test_equality(item1: &Gc<VarValues>, item2: &Gc<VarValues>) -> LangResult
         (AstStr(_, Some(n1)), AstStr(_, Some(n2))) => {
             Ok(n1 == n2)
         },
-        (Str(s1), Str(s2)) |
-        (AstStr(s1, _), Str(s2)) |
-        (Str(s1), AstStr(s2, _)) |
+        (Str(s1), Str(s2)) => {
+            Ok(s1 == s2)
+        },
+        (AstStr(s1, _), Str(s2)) => {
+            Ok(**s1 == **s2)
+        },
+        (Str(s1), AstStr(s2, _)) => {
+            Ok(**s1 == **s2)
+        },
         (AstStr(s1, None), AstStr(s2, None)) => {
             Ok(s1 == s2)
         },
@@ -39,75 +50,198 @@ pub fn test_equality(item1: &Gc<VarValues>, item2: &Gc<VarValues>) -> LangResult
         (Num(n), Str(s)) => {
             Ok(s == &f64_to_string(*n))
         },
+        (Set(s1), Set(s2)) => {
+            Ok(s1 == s2)
+        },
+        // raw text is compared as the text it holds
+        (Raw(s), other) |
+        (other, Raw(s)) => {
+            match other {
+                Nil | Str(_) | AstStr(..) | Num(_) | Raw(_) => Ok(*s == other.to_string()),
+                _ => Ok(false),
+            }
+        },
+        // callables compare by identity, using the Gc pointer rather
+        // than the fn pointer since the latter isn't guaranteed unique
+        (Func(..), Func(..)) |
+        (RustFunc(_), RustFunc(_)) |
+        (RustClosure(_), RustClosure(_)) => {
+            Ok(std::ptr::eq(&**item1, &**item2))
+        },
         (_, _) => {
             Ok(false)
         },
     }
 }
 
-pub fn not_func(_ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+// hashes a value so that anything test_equality treats as equal hashes
+// the same: scalars go through their canonical string form, since nil
+// equals "" and numbers equal their printed form. lists and maps hash
+// their contents, and callables hash by identity like they compare
+fn hash_value<H: Hasher>(val: &Gc<VarValues>, seen: &mut Vec<*const VarValues>, state: &mut H) -> LangResult<()> {
+    use VarValues::*;
+    let val_ref = borrow_val(val)?;
+    let ptr = &*val_ref as *const VarValues;
+    match &*val_ref {
+        Nil => "".hash(state),
+        Str(s) | Raw(s) => s.hash(state),
+        AstStr(_, Some(n)) |
+        Num(n) => f64_to_string(*n).hash(state),
+        AstStr(s, None) => s.hash(state),
+        List(_) | Map(_) if seen.contains(&ptr) => {
+            // a list that contains itself only needs its shape marked
+            "...".hash(state);
+        },
+        List(vals) => {
+            seen.push(ptr);
+            state.write_u8(b'[');
+            state.write_usize(vals.len());
+            for v in vals.iter() {
+                hash_value(v, seen, state)?;
+            }
+            seen.pop();
+        },
+        Map(vals) => {
+            seen.push(ptr);
+            state.write_u8(b'{');
+            state.write_usize(vals.len());
+            let mut keys: Vec<&String> = vals.keys().collect();
+            keys.sort();
+            for key in keys {
+                key.hash(state);
+                hash_value(&vals[key], seen, state)?;
+            }
+            seen.pop();
+        },
+        Set(vals) => {
+            state.write_u8(b's');
+            let mut items: Vec<&String> = vals.iter().collect();
+            items.sort();
+            items.hash(state);
+        },
+        Range(start, end, step) => {
+            state.write_u8(b'r');
+            for n in &[start, end, step] {
+                n.to_bits().hash(state);
+            }
+        },
+        CatchResult(status, v) => {
+            state.write_u8(b'c');
+            status.hash(state);
+            hash_value(v, seen, state)?;
+        },
+        Func(..) | RustFunc(_) | RustClosure(_) | Iterator(_) => {
+            std::ptr::hash(&**val, state);
+        },
+    }
+    Ok(())
+}
+
+pub fn hash_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    if args.len() != 1 {
+        return throw_string!("<hash:expected 1 arg, got {}>", args.len());
+    }
+    // DefaultHasher::new always uses the same keys, so the result is
+    // stable between runs of the same build
+    let mut state = DefaultHasher::new();
+    hash_value(&args[0], &mut Vec::new(), &mut state)?;
+    Ok(ctx.new_str(format!("{:016x}", state.finish())))
+}
+
+pub fn not_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
     if args.len() != 1 {
         return throw_string!("<eq:expected 1 arg, got {}>", args.len());
     }
     let bool_val: bool = (&*borrow_val(&args[0])?).into();
-    return Ok(
-        new_value(
-            VarValues::Num(if !bool_val {1.0} else {0.0})
-        )
-    );
+    return Ok(ctx.new_num(if !bool_val {1.0} else {0.0}));
 }
 
-pub fn eq_func(_ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+pub fn eq_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
     if args.len() < 2 {
         return throw_string!("<eq:expected 2 args, got {}>", args.len());
     }
 
     let mut item1 = &args[0];
     for item2 in &args[1..] {
-        use VarValues::*;
         if !test_equality(item1, item2)? {
-            return Ok(new_value(Num(0.0)));
+            return Ok(ctx.new_num(0.0));
         }
         item1 = item2;
     }
 
-    Ok(new_value(VarValues::Num(1.0)))
+    Ok(ctx.new_num(1.0))
 }
 
-pub fn ne_func(_ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+// pretty with no indent puts each item on a line of its own,
+// which is joined back up to fit in a thrown message
+fn one_line(val: &Gc<VarValues>) -> LangResult<String> {
+    Ok(pretty_string(val, 0)?.replace('\n', " "))
+}
+
+// {asserteq:actual:expected;} gives nil, or throws with both values written
+// out in full, so lists and maps show what's in them
+pub fn asserteq_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    if args.len() != 2 {
+        return throw_string!("<asserteq:expected 2 args, got {}>", args.len());
+    }
+    if test_equality(&args[0], &args[1])? {
+        return Ok(ctx.new_nil());
+    }
+    throw_string!(
+        "<asserteq:expected {}, got {}>",
+        one_line(&args[1])?,
+        one_line(&args[0])?
+    )
+}
+
+// {ensure:cond:msg;} is a guard for use outside of tests: it throws msg
+// itself, rather than a message of its own, so callers can catch it as is
+pub fn ensure_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    if args.len() != 2 {
+        return throw_string!("<ensure:expected 2 args, got {}>", args.len());
+    }
+    let test: bool = (&*borrow_val(&args[0])?).into();
+    if test {
+        Ok(ctx.new_nil())
+    } else {
+        Err(LangError::Throw(args[1]))
+    }
+}
+
+pub fn ne_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
     if args.len() < 2 {
         return throw_string!("<ne:expected 2 args, got {}>", args.len());
     }
 
     let mut item1 = &args[0];
     for item2 in &args[1..] {
-        use VarValues::*;
         if test_equality(item1, item2)? {
-            return Ok(new_value(Num(0.0)));
+            return Ok(ctx.new_num(0.0));
         }
         item1 = item2;
     }
 
-    Ok(new_value(VarValues::Num(1.0)))
+    Ok(ctx.new_num(1.0))
 }
 
+// comparisons chain like eq does, so {lt:a:b:c;} tests a < b and b < c
+// it stops at the first pair that fails, without converting the rest
 macro_rules! num_comp_func {
     ($func_name:ident, $lang_name:expr, $op:tt) => {
-        pub fn $func_name(_ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
-            if args.len() != 2 {
-                return throw_string!(concat!("<", $lang_name, ":expected 2 args, got {}>"), args.len());
+        pub fn $func_name(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+            if args.len() < 2 {
+                return throw_string!(concat!("<", $lang_name, ":expected 2+ args, got {}>"), args.len());
             }
-            use VarValues::Num;
             let mut item1 = val_to_f64(&args[0], $lang_name)?;
             for item2 in &args[1..] {
                 let item2 = val_to_f64(item2, $lang_name)?;
                 if !(item1 $op item2) {
-                    return Ok(new_value(Num(0.0)));
+                    return Ok(ctx.new_num(0.0));
                 }
                 item1 = item2;
             }
 
-            Ok(new_value(Num(1.0)))
+            Ok(ctx.new_num(1.0))
         }
     }
 }
@@ -117,6 +251,25 @@ num_comp_func!(gt_func, "gt", >);
 num_comp_func!(le_func, "le", <=);
 num_comp_func!(ge_func, "ge", >=);
 
+// inclusive unless the optional fourth arg is truthy
+pub fn between_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    if args.len() != 3 && args.len() != 4 {
+        return throw_string!("<between:expected 3-4 args, got {}>", args.len());
+    }
+    let val = val_to_f64(&args[0], "between")?;
+    let low = val_to_f64(&args[1], "between")?;
+    let high = val_to_f64(&args[2], "between")?;
+    let exclusive = match args.get(3) {
+        Some(arg) => (&*borrow_val(arg)?).into(),
+        None => false,
+    };
+    let ret = match exclusive {
+        true => low < val && val < high,
+        false => low <= val && val <= high,
+    };
+    Ok(ctx.new_num(if ret {1.0} else {0.0}))
+}
+
 pub fn and_func(_ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
     if args.len() < 2 {
         return throw_string!("<and:expected 2+ args, got {}>", args.len());
@@ -0,0 +1,126 @@
+use std::rc::Rc;
+use crate::throw_string;
+use crate::lang_core::interp::{
+    LangResult,
+    LangError,
+    VarValues,
+    Context,
+    Gc,
+    new_value,
+    borrow_val
+};
+
+// a field in quotes can hold commas, newlines and "" for a quote of its own,
+// and a newline at the very end doesn't start another row
+fn parse_csv(text: &str) -> Result<Vec<Vec<String>>, &'static str> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut chars = text.chars().peekable();
+    if chars.peek().is_none() {
+        return Ok(rows);
+    }
+    let mut field_start = true;
+    loop {
+        if field_start && chars.peek() == Some(&'"') {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        field.push('"');
+                    },
+                    Some('"') => break,
+                    Some(c) => field.push(c),
+                    None => return Err("unterminated quote"),
+                }
+            }
+        }
+        field_start = matches!(chars.peek(), Some(',') | Some('\n'));
+        match chars.next() {
+            Some(',') => {
+                row.push(std::mem::take(&mut field));
+            },
+            Some('\r') if chars.peek() == Some(&'\n') => {},
+            Some('\n') => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+                if chars.peek().is_none() {
+                    return Ok(rows);
+                }
+            },
+            Some('"') => return Err("unexpected quote"),
+            Some(c) => field.push(c),
+            None => {
+                row.push(field);
+                rows.push(row);
+                return Ok(rows);
+            },
+        }
+    }
+}
+
+fn write_field(out: &mut String, field: &str) {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        out.push('"');
+        out.push_str(&field.replace('"', "\"\""));
+        out.push('"');
+    } else {
+        out.push_str(field);
+    }
+}
+
+// {fromcsv:text;} gives a list of rows, each a list of strings
+pub fn fromcsv_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    if args.len() != 1 {
+        return throw_string!("<fromcsv:expected 1 arg, got {}>", args.len());
+    }
+    let text = borrow_val(&args[0])?.to_string();
+    let rows = match parse_csv(&text) {
+        Ok(rows) => rows,
+        Err(msg) => {
+            return throw_string!("<fromcsv:{}>", msg);
+        }
+    };
+    let rows = rows.into_iter()
+        .map(|row| {
+            let fields = row.into_iter().map(|field| ctx.new_str(field)).collect();
+            new_value(VarValues::List(Rc::new(fields)))
+        })
+        .collect();
+    Ok(new_value(VarValues::List(Rc::new(rows))))
+}
+
+// {tocsv:rows;} is the reverse of fromcsv, only quoting the fields that need
+// it, with a newline between each row but none after the last
+pub fn tocsv_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    if args.len() != 1 {
+        return throw_string!("<tocsv:expected 1 arg, got {}>", args.len());
+    }
+    let rows = match &*borrow_val(&args[0])? {
+        VarValues::List(rows) => rows.clone(),
+        _ => {
+            return throw_string!("<tocsv:expected list>");
+        }
+    };
+    let mut out = String::new();
+    for (i, row) in rows.iter().enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        match &*borrow_val(row)? {
+            VarValues::List(fields) => {
+                for (j, field) in fields.iter().enumerate() {
+                    if j > 0 {
+                        out.push(',');
+                    }
+                    write_field(&mut out, &borrow_val(field)?.to_string());
+                }
+            },
+            _ => {
+                return throw_string!("<tocsv:expected list of lists>");
+            }
+        }
+    }
+    Ok(ctx.new_str(out))
+}
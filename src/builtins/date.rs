@@ -0,0 +1,174 @@
+use crate::throw_string;
+use crate::lang_core::interp::{
+    LangResult,
+    LangError,
+    VarValues,
+    Context,
+    Gc,
+    new_value,
+    borrow_val
+};
+use crate::builtins::math::val_to_f64;
+
+// dates are all in utc, worked out from the days since 1970-01-01 with the
+// proleptic gregorian calendar, as in http://howardhinnant.github.io/date_algorithms.html
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe/1460 + doe/36524 - doe/146096) / 365;
+    let doy = doe - (365*yoe + yoe/4 - yoe/100);
+    let mp = (5*doy + 2) / 153;
+    let day = doy - (153*mp + 2)/5 + 1;
+    let month = if mp < 10 {mp + 3} else {mp - 9};
+    let year = yoe + era*400 + if month <= 2 {1} else {0};
+    (year, month, day)
+}
+
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 {year - 1} else {year};
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let doy = (153*(if month > 2 {month - 3} else {month + 9}) + 2)/5 + day - 1;
+    let doe = yoe*365 + yoe/4 - yoe/100 + doy;
+    era*146097 + doe - 719468
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+fn format_args(args: &[Gc<VarValues>], func_name: &str) -> LangResult<String> {
+    if args.len() != 2 {
+        return throw_string!("<{}:expected 2 args, got {}>", func_name, args.len());
+    }
+    Ok(borrow_val(&args[1])?.to_string())
+}
+
+// {strftime:timestamp:format;} understands %Y %m %d %H %M %S and %%, with
+// %F and %T short for %Y-%m-%d and %H:%M:%S
+pub fn strftime_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    let format = format_args(&args, "strftime")?;
+    let timestamp = val_to_f64(&args[0], "strftime")?;
+    // hundreds of millions of years either way, well short of overflowing
+    if !timestamp.is_finite() || timestamp.abs() > 1e15 {
+        return throw_string!("<strftime:invalid timestamp>");
+    }
+    let secs = timestamp.floor() as i64;
+    let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+    let time = secs.rem_euclid(86400);
+    let (hour, minute, second) = (time / 3600, time / 60 % 60, time % 60);
+
+    let mut out = String::with_capacity(format.len());
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => out.push_str(&format!("{:04}", year)),
+            Some('m') => out.push_str(&format!("{:02}", month)),
+            Some('d') => out.push_str(&format!("{:02}", day)),
+            Some('H') => out.push_str(&format!("{:02}", hour)),
+            Some('M') => out.push_str(&format!("{:02}", minute)),
+            Some('S') => out.push_str(&format!("{:02}", second)),
+            Some('F') => out.push_str(&format!("{:04}-{:02}-{:02}", year, month, day)),
+            Some('T') => out.push_str(&format!("{:02}:{:02}:{:02}", hour, minute, second)),
+            Some('%') => out.push('%'),
+            _ => {
+                return throw_string!("<strftime:invalid format>");
+            }
+        }
+    }
+    Ok(ctx.new_str(out))
+}
+
+// takes up to max digits, with a sign allowed for the year
+fn take_num(s: &mut &str, max: usize, signed: bool) -> Option<i64> {
+    let neg = signed && s.starts_with('-');
+    let start = if neg {1} else {0};
+    let len = s[start..].bytes().take(max).take_while(|b| b.is_ascii_digit()).count();
+    if len == 0 {
+        return None;
+    }
+    let n: i64 = s[start..start+len].parse().ok()?;
+    *s = &s[start+len..];
+    Some(if neg {-n} else {n})
+}
+
+// {strptime:text:format;} is the reverse of strftime, giving the timestamp
+// for a date written in the format; any field that's left out is taken
+// from 1970-01-01 00:00:00
+pub fn strptime_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    let format = format_args(&args, "strptime")?;
+    let text = borrow_val(&args[0])?.to_string();
+
+    // the format as the fields and literal chars it matches in order, each
+    // marked true if it's a field
+    let mut pieces = Vec::with_capacity(format.len());
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            pieces.push((false, c));
+            continue;
+        }
+        match chars.next() {
+            Some('%') => pieces.push((false, '%')),
+            Some(f @ 'Y') | Some(f @ 'm') | Some(f @ 'd') |
+            Some(f @ 'H') | Some(f @ 'M') | Some(f @ 'S') => pieces.push((true, f)),
+            Some('F') => pieces.extend(&[(true, 'Y'), (false, '-'), (true, 'm'), (false, '-'), (true, 'd')]),
+            Some('T') => pieces.extend(&[(true, 'H'), (false, ':'), (true, 'M'), (false, ':'), (true, 'S')]),
+            _ => {
+                return throw_string!("<strptime:invalid format>");
+            }
+        }
+    }
+
+    let (mut year, mut month, mut day) = (1970, 1, 1);
+    let (mut hour, mut minute, mut second) = (0, 0, 0);
+    let mut rest = &text[..];
+    for (is_field, c) in pieces {
+        let field = match is_field {
+            true => c,
+            false => {
+                match rest.strip_prefix(c) {
+                    Some(r) => rest = r,
+                    None => {
+                        return throw_string!("<strptime:does not match format>");
+                    }
+                }
+                continue;
+            }
+        };
+        let n = match take_num(&mut rest, if field == 'Y' {4} else {2}, field == 'Y') {
+            Some(n) => n,
+            None => {
+                return throw_string!("<strptime:does not match format>");
+            }
+        };
+        match field {
+            'Y' => year = n,
+            'm' => month = n,
+            'd' => day = n,
+            'H' => hour = n,
+            'M' => minute = n,
+            _ => second = n,
+        }
+    }
+    if !rest.is_empty() {
+        return throw_string!("<strptime:does not match format>");
+    }
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month)
+        || hour > 23 || minute > 59 || second > 59 {
+        return throw_string!("<strptime:invalid date>");
+    }
+    let days = days_from_civil(year, month, day);
+    Ok(ctx.new_num((days*86400 + hour*3600 + minute*60 + second) as f64))
+}
@@ -0,0 +1,51 @@
+use crate::throw_string;
+use crate::lang_core::interp::{
+    LangResult,
+    LangError,
+    Context,
+    VarValues,
+    Gc,
+    new_value,
+    borrow_val
+};
+use crate::builtins::string::pretty_string;
+
+// writes the value to stderr and hands it straight back, so it can be
+// dropped into the middle of any expression or pipe
+pub fn inspect_func(_ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    if args.is_empty() || args.len() > 2 {
+        return throw_string!("<inspect:expected 1-2 args, got {}>", args.len());
+    }
+    let text = pretty_string(&args[0], 2)?;
+    match args.get(1) {
+        Some(label) => eprintln!("{}: {}", borrow_val(label)?.to_string(), text),
+        None => eprintln!("{}", text),
+    }
+    Ok(Gc::clone(&args[0]))
+}
+
+// the same names that matchtype takes, such as list, num or nil
+pub fn type_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    if args.len() != 1 {
+        return throw_string!("<type:expected 1 arg, got {}>", args.len());
+    }
+    let name = borrow_val(&args[0])?.type_name();
+    Ok(ctx.new_str(name.to_owned()))
+}
+
+// how many user functions deep the call to depth is, 0 outside of any
+pub fn depth_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    if !args.is_empty() {
+        return throw_string!("<depth:expected 0 args, got {}>", args.len());
+    }
+    Ok(ctx.new_num(ctx.call_depth as f64))
+}
+
+// the number of values on the interpreter's stack, for debugging the
+// interpreter itself, so it's only registered in debug mode
+pub fn stack_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    if !args.is_empty() {
+        return throw_string!("<__stack:expected 0 args, got {}>", args.len());
+    }
+    Ok(ctx.new_num(ctx.stack.len() as f64))
+}
@@ -0,0 +1,82 @@
+use crate::throw_string;
+use crate::lang_core::interp::{
+    LangResult,
+    LangError,
+    VarValues,
+    Context,
+    Gc,
+    new_value,
+    borrow_val
+};
+
+// the optional second arg picks form encoding, where a space is a +
+fn url_args(args: &[Gc<VarValues>], func_name: &str) -> LangResult<(String, bool)> {
+    if args.is_empty() || args.len() > 2 {
+        return throw_string!("<{}:expected 1-2 args, got {}>", func_name, args.len());
+    }
+    let s = borrow_val(&args[0])?.to_string();
+    let form = match args.get(1) {
+        Some(arg) => (&*borrow_val(arg)?).into(),
+        None => false,
+    };
+    Ok((s, form))
+}
+
+// everything but the unreserved characters of RFC 3986 is escaped,
+// going by the bytes of its utf-8 form
+pub fn urlencode_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    let (s, form) = url_args(&args, "urlencode")?;
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                out.push(b as char);
+            },
+            b' ' if form => {
+                out.push('+');
+            },
+            _ => {
+                out.push_str(&format!("%{:02X}", b));
+            },
+        }
+    }
+    Ok(ctx.new_str(out))
+}
+
+fn hex_digit(b: u8) -> Option<u8> {
+    (b as char).to_digit(16).map(|d| d as u8)
+}
+
+pub fn urldecode_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    let (s, form) = url_args(&args, "urldecode")?;
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let high = bytes.get(i+1).copied().and_then(hex_digit);
+                let low = bytes.get(i+2).copied().and_then(hex_digit);
+                match (high, low) {
+                    (Some(high), Some(low)) => out.push(high << 4 | low),
+                    _ => {
+                        return throw_string!("<urldecode:invalid escape>");
+                    }
+                }
+                i += 3;
+            },
+            b'+' if form => {
+                out.push(b' ');
+                i += 1;
+            },
+            b => {
+                out.push(b);
+                i += 1;
+            },
+        }
+    }
+    match String::from_utf8(out) {
+        Ok(s) => Ok(ctx.new_str(s)),
+        Err(_) => throw_string!("<urldecode:invalid utf-8>"),
+    }
+}
@@ -0,0 +1,52 @@
+use crate::throw_string;
+use crate::lang_core::interp::{
+    LangResult,
+    LangError,
+    VarValues,
+    Context,
+    Gc,
+    new_value,
+    borrow_val
+};
+use crate::builtins::math::val_to_f64;
+
+// a function that keeps taking args until it has at least arity of them,
+// then calls func with all of them at once
+fn curried(func: Gc<VarValues>, arity: usize, collected: Vec<Gc<VarValues>>) -> Gc<VarValues> {
+    let method = move |ctx: &mut Context, args: Vec<Gc<VarValues>>| {
+        let mut all_args = collected.clone();
+        all_args.extend(args);
+        if all_args.len() >= arity {
+            ctx.call_value(&func, all_args)
+        } else {
+            Ok(curried(Gc::clone(&func), arity, all_args))
+        }
+    };
+    new_value(VarValues::RustClosure(Box::new(method)))
+}
+
+pub fn curry_func(_ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    if args.is_empty() || args.len() > 2 {
+        return throw_string!("<curry:expected 1-2 args, got {}>", args.len());
+    }
+    // builtins can't say how many args they take, but most need at least 2
+    let default_arity = match &*borrow_val(&args[0])? {
+        VarValues::Func(_, names, _, _, _) => names.len(),
+        VarValues::RustFunc(_) |
+        VarValues::RustClosure(_) => 2,
+        _ => {
+            return throw_string!("<curry:expected function>");
+        }
+    };
+    let arity = match args.get(1) {
+        Some(arg) => {
+            let n = val_to_f64(arg, "curry")?;
+            if n < 0.0 || n.fract() != 0.0 || !n.is_finite() {
+                return throw_string!("<curry:invalid arity>");
+            }
+            n as usize
+        },
+        None => default_arity,
+    };
+    Ok(curried(Gc::clone(&args[0]), arity, Vec::new()))
+}
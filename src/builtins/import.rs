@@ -0,0 +1,59 @@
+use std::fs;
+use crate::throw_string;
+use crate::lang_core::{parse, bytecode};
+use crate::lang_core::interp::{
+    LangResult,
+    LangError,
+    VarValues,
+    Context,
+    Gc,
+    new_value,
+    borrow_val
+};
+
+// runs another file in the scope import was called from, so anything it
+// defines is usable afterwards, and returns whatever it outputs
+pub fn import_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    if args.len() != 1 {
+        return throw_string!("<import:expected 1 arg, got {}>", args.len());
+    }
+    if !ctx.fs_access {
+        return throw_string!("<import:filesystem access disabled>");
+    }
+    let filename = borrow_val(&args[0])?.to_string();
+    let path = match fs::canonicalize(&filename) {
+        Ok(path) => path,
+        Err(_) => {
+            return throw_string!("<import:could not read file>");
+        }
+    };
+    if ctx.importing.contains(&path) {
+        return throw_string!("<import:circular import>");
+    }
+    let source = match fs::read_to_string(&path) {
+        Ok(source) => source,
+        Err(_) => {
+            return throw_string!("<import:could not read file>");
+        }
+    };
+    let ast = match parse::run_parser(&source) {
+        Ok(ast) => ast,
+        Err(_) => {
+            return throw_string!("<import:could not parse file>");
+        }
+    };
+    let program = match bytecode::generate_bytecode(&ast, &ctx.rebound_names()?) {
+        Ok(program) => program,
+        Err(_) => {
+            return throw_string!("<import:syntax error>");
+        }
+    };
+    if bytecode::validate_program(&program).is_err() {
+        return throw_string!("<import:invalid bytecode>");
+    }
+
+    ctx.importing.push(path);
+    let ret = ctx.run_collected(&program);
+    ctx.importing.pop();
+    ret
+}
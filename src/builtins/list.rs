@@ -0,0 +1,625 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use crate::throw_string;
+use crate::lang_core::interp::{
+    LangResult,
+    LangError,
+    VarValues,
+    Context,
+    Gc,
+    new_value,
+    borrow_val,
+    borrow_val_mut,
+    range_len,
+    wrap_index,
+    string_to_f64,
+    IterFn
+};
+use crate::builtins::math::val_to_f64;
+use crate::builtins::boolean::test_equality;
+
+fn range_args(args: &[Gc<VarValues>], func_name: &str) -> LangResult<(f64, f64, f64)> {
+    let (start, end, step) = match args.len() {
+        1 => (0.0, val_to_f64(&args[0], func_name)?, 1.0),
+        2 => (val_to_f64(&args[0], func_name)?, val_to_f64(&args[1], func_name)?, 1.0),
+        3 => (
+            val_to_f64(&args[0], func_name)?,
+            val_to_f64(&args[1], func_name)?,
+            val_to_f64(&args[2], func_name)?
+        ),
+        n => {
+            return throw_string!("<{}:expected 1-3 args, got {}>", func_name, n);
+        }
+    };
+    if !start.is_finite() || !end.is_finite() || !step.is_finite() {
+        return throw_string!("<{}:non-finite bound>", func_name);
+    }
+    if step == 0.0 {
+        return throw_string!("<{}:zero step>", func_name);
+    }
+    Ok((start, end, step))
+}
+
+pub fn range_func(_ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    let (start, end, step) = range_args(&args, "range")?;
+
+    // multiply rather than accumulate, so float steps don't drift
+    let vals = (0..range_len(start, end, step))
+        .map(|i| new_value(VarValues::Num(start + i as f64 * step)))
+        .collect();
+    Ok(new_value(VarValues::List(Rc::new(vals))))
+}
+
+pub fn irange_func(_ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    let (start, end, step) = range_args(&args, "irange")?;
+    Ok(new_value(VarValues::Range(start, end, step)))
+}
+
+fn quantify(ctx: &mut Context, args: Vec<Gc<VarValues>>, func_name: &str, stop_on: bool) -> LangResult<Gc<VarValues>> {
+    if args.is_empty() || args.len() > 2 {
+        return throw_string!("<{}:expected 1-2 args, got {}>", func_name, args.len());
+    }
+    // copy the items out, so the predicate is free to modify the list
+    let items = match &*borrow_val(&args[0])? {
+        VarValues::List(vals) => vals.to_vec(),
+        _ => {
+            return throw_string!("<{}:expected list>", func_name);
+        }
+    };
+    let pred = args.get(1);
+
+    for item in items {
+        let val = match pred {
+            Some(func) => ctx.call_value(func, vec![item])?,
+            None => item,
+        };
+        let test: bool = (&*borrow_val(&val)?).into();
+        if test == stop_on {
+            return Ok(new_value(VarValues::Num(if stop_on {1.0} else {0.0})));
+        }
+    }
+    Ok(new_value(VarValues::Num(if stop_on {0.0} else {1.0})))
+}
+
+pub fn any_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    quantify(ctx, args, "any", true)
+}
+
+pub fn all_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    quantify(ctx, args, "all", false)
+}
+
+pub fn fold_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    if args.len() != 3 {
+        return throw_string!("<fold:expected 3 args, got {}>", args.len());
+    }
+    let items = match &*borrow_val(&args[2])? {
+        VarValues::List(vals) => vals.to_vec(),
+        _ => {
+            return throw_string!("<fold:expected list>");
+        }
+    };
+
+    let mut acc = Gc::clone(&args[1]);
+    for item in items {
+        acc = ctx.call_value(&args[0], vec![acc, item])?;
+    }
+    Ok(acc)
+}
+
+// keys compare as numbers when both are numbers, and by their text otherwise
+fn compare_keys(key1: &Gc<VarValues>, key2: &Gc<VarValues>) -> LangResult<Ordering> {
+    let (key1, key2) = (borrow_val(key1)?, borrow_val(key2)?);
+    let as_num = |v: &VarValues| match v {
+        VarValues::Num(n) |
+        VarValues::AstStr(_, Some(n)) => Some(*n),
+        _ => None,
+    };
+    Ok(match (as_num(&key1), as_num(&key2)) {
+        (Some(n1), Some(n2)) => n1.partial_cmp(&n2).unwrap_or(Ordering::Equal),
+        _ => key1.to_string().cmp(&key2.to_string()),
+    })
+}
+
+// the key is only worked out once for each item, and the first of any
+// items with equal keys wins
+fn extreme_by(ctx: &mut Context, args: Vec<Gc<VarValues>>, func_name: &str, wanted: Ordering) -> LangResult<Gc<VarValues>> {
+    if args.len() != 2 {
+        return throw_string!("<{}:expected 2 args, got {}>", func_name, args.len());
+    }
+    let items = match &*borrow_val(&args[0])? {
+        VarValues::List(vals) => vals.to_vec(),
+        _ => {
+            return throw_string!("<{}:expected list>", func_name);
+        }
+    };
+    let mut items = items.into_iter();
+    let mut best = match items.next() {
+        Some(item) => item,
+        None => {
+            return throw_string!("<{}:empty list>", func_name);
+        }
+    };
+    let mut best_key = ctx.call_value(&args[1], vec![Gc::clone(&best)])?;
+    for item in items {
+        let key = ctx.call_value(&args[1], vec![Gc::clone(&item)])?;
+        if compare_keys(&key, &best_key)? == wanted {
+            best = item;
+            best_key = key;
+        }
+    }
+    Ok(best)
+}
+
+pub fn minby_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    extreme_by(ctx, args, "minby", Ordering::Less)
+}
+
+pub fn maxby_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    extreme_by(ctx, args, "maxby", Ordering::Greater)
+}
+
+// {groupby:list:f;} gives a map from each key f gives, in its string form,
+// to a list of the items with that key, in the order they came
+pub fn groupby_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    if args.len() != 2 {
+        return throw_string!("<groupby:expected 2 args, got {}>", args.len());
+    }
+    let items = match &*borrow_val(&args[0])? {
+        VarValues::List(vals) => vals.to_vec(),
+        _ => {
+            return throw_string!("<groupby:expected list>");
+        }
+    };
+    let mut groups: HashMap<String, Vec<Gc<VarValues>>> = HashMap::new();
+    for item in items {
+        let key = ctx.call_value(&args[1], vec![Gc::clone(&item)])?;
+        let key = borrow_val(&key)?.to_string();
+        groups.entry(key).or_default().push(item);
+    }
+    let groups = groups.into_iter()
+        .map(|(k, v)| (k, new_value(VarValues::List(Rc::new(v)))))
+        .collect();
+    Ok(new_value(VarValues::Map(Rc::new(groups))))
+}
+
+// {counter:list;} maps each distinct item, in its string form, to how many
+// times it comes up
+pub fn counter_func(_ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    if args.len() != 1 {
+        return throw_string!("<counter:expected 1 arg, got {}>", args.len());
+    }
+    let mut counts: HashMap<String, f64> = HashMap::new();
+    match &*borrow_val(&args[0])? {
+        VarValues::List(vals) => {
+            for v in vals.iter() {
+                *counts.entry(borrow_val(v)?.to_string()).or_default() += 1.0;
+            }
+        },
+        _ => {
+            return throw_string!("<counter:expected list>");
+        }
+    }
+    let counts = counts.into_iter()
+        .map(|(k, n)| (k, new_value(VarValues::Num(n))))
+        .collect();
+    Ok(new_value(VarValues::Map(Rc::new(counts))))
+}
+
+pub fn set_func(_ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    // items are keyed by their string form, the same way map keys are
+    let mut vals = HashSet::with_capacity(args.len());
+    for arg in &args {
+        vals.insert(borrow_val(arg)?.to_string());
+    }
+    Ok(new_value(VarValues::Set(vals)))
+}
+
+fn holds_containers(vals: &mut dyn Iterator<Item=&Gc<VarValues>>) -> LangResult<bool> {
+    for v in vals {
+        if let VarValues::List(_) | VarValues::Map(_) | VarValues::Set(_) = &*borrow_val(v)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+// the copies already made are kept, so a list that holds itself is copied
+// into one that holds the copy, the same as python's deepcopy
+fn copy_value(val: &Gc<VarValues>, copied: &mut Vec<(*const VarValues, Gc<VarValues>)>) -> LangResult<Gc<VarValues>> {
+    let val_ref = borrow_val(val)?;
+    let ptr = &*val_ref as *const VarValues;
+    if let Some((_, copy)) = copied.iter().find(|(p, _)| *p == ptr) {
+        return Ok(Gc::clone(copy));
+    }
+    let copy = match &*val_ref {
+        VarValues::List(_) | VarValues::Map(_) => {
+            let copy = new_value(VarValues::Nil);
+            copied.push((ptr, Gc::clone(&copy)));
+            copy
+        },
+        VarValues::Set(vals) => {
+            return Ok(new_value(VarValues::Set(vals.clone())));
+        },
+        // nothing else can be changed in place, so it can be shared as is
+        _ => {
+            return Ok(Gc::clone(val));
+        }
+    };
+    // when nothing inside needs copying of its own, the storage is shared
+    // until either side changes it, rather than cloned up front
+    let new_val = match &*val_ref {
+        VarValues::List(vals) if !holds_containers(&mut vals.iter())? => {
+            VarValues::List(Rc::clone(vals))
+        },
+        VarValues::List(vals) => {
+            let mut items = Vec::with_capacity(vals.len());
+            for v in vals.iter() {
+                items.push(copy_value(v, copied)?);
+            }
+            VarValues::List(Rc::new(items))
+        },
+        VarValues::Map(vals) if !holds_containers(&mut vals.values())? => {
+            VarValues::Map(Rc::clone(vals))
+        },
+        VarValues::Map(vals) => {
+            let mut items = HashMap::with_capacity(vals.len());
+            for (k, v) in vals.iter() {
+                items.insert(k.clone(), copy_value(v, copied)?);
+            }
+            VarValues::Map(Rc::new(items))
+        },
+        _ => unreachable!(),
+    };
+    *borrow_val_mut(&copy)? = new_val;
+    Ok(copy)
+}
+
+// {copy:val;} gives a deep copy, so changing it leaves the original alone
+pub fn copy_func(_ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    if args.len() != 1 {
+        return throw_string!("<copy:expected 1 arg, got {}>", args.len());
+    }
+    copy_value(&args[0], &mut Vec::new())
+}
+
+// the same cap as zfill, on the total length of the result
+const MAX_REPEAT_LEN: f64 = 1_048_576.0;
+
+pub fn repeat_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    if args.len() != 2 {
+        return throw_string!("<repeat:expected 2 args, got {}>", args.len());
+    }
+    let count = val_to_f64(&args[1], "repeat")?;
+    if !count.is_finite() || count.fract() != 0.0 || count < 0.0 {
+        return throw_string!("<repeat:invalid count>");
+    }
+    match &*borrow_val(&args[0])? {
+        VarValues::List(vals) => {
+            if vals.len() as f64 * count > MAX_REPEAT_LEN {
+                return throw_string!("<repeat:result too long>");
+            }
+            // the items themselves are shared, like python's [x]*n
+            let mut ret = Vec::with_capacity(vals.len() * count as usize);
+            for _ in 0..count as usize {
+                ret.extend(vals.iter().copied());
+            }
+            Ok(new_value(VarValues::List(Rc::new(ret))))
+        },
+        v => {
+            let s = v.to_string();
+            if s.chars().count() as f64 * count > MAX_REPEAT_LEN {
+                return throw_string!("<repeat:result too long>");
+            }
+            Ok(ctx.new_str(s.repeat(count as usize)))
+        },
+    }
+}
+
+// {generate:n:f;} is the list of f called with each index from 0 up to n
+pub fn generate_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    if args.len() != 2 {
+        return throw_string!("<generate:expected 2 args, got {}>", args.len());
+    }
+    let count = val_to_f64(&args[0], "generate")?;
+    if !count.is_finite() || count.fract() != 0.0 || count < 0.0 {
+        return throw_string!("<generate:invalid count>");
+    }
+    if count > MAX_REPEAT_LEN {
+        return throw_string!("<generate:result too long>");
+    }
+    match &*borrow_val(&args[1])? {
+        VarValues::Func(..) |
+        VarValues::RustFunc(_) |
+        VarValues::RustClosure(_) => {},
+        _ => {
+            return throw_string!("<generate:expected function>");
+        }
+    }
+    let mut ret = Vec::with_capacity(count as usize);
+    for i in 0..count as usize {
+        let index = ctx.new_num(i as f64);
+        ret.push(ctx.call_value(&args[1], vec![index])?);
+    }
+    Ok(new_value(VarValues::List(Rc::new(ret))))
+}
+
+// steps through a list, range or iterator without copying it out first
+fn iter_items(val: &Gc<VarValues>, func_name: &str) -> LangResult<IterFn> {
+    let mut index = 0;
+    let is_list = match &*borrow_val(val)? {
+        VarValues::List(_) => true,
+        VarValues::Iterator(_) => false,
+        VarValues::Range(start, end, step) => {
+            let (start, step) = (*start, *step);
+            let len = range_len(start, *end, step);
+            return Ok(Box::new(move |ctx| {
+                if index == len {
+                    return Ok(None);
+                }
+                index += 1;
+                Ok(Some(ctx.new_num(start + (index - 1) as f64 * step)))
+            }));
+        },
+        _ => {
+            return throw_string!("<{}:expected list, range or iterator>", func_name);
+        }
+    };
+    let val = Gc::clone(val);
+    if is_list {
+        Ok(Box::new(move |_ctx| {
+            let item = match &*borrow_val(&val)? {
+                VarValues::List(vals) => vals.get(index).copied(),
+                _ => unreachable!(),
+            };
+            index += 1;
+            Ok(item)
+        }))
+    } else {
+        Ok(Box::new(move |ctx| {
+            match &mut *borrow_val_mut(&val)? {
+                VarValues::Iterator(f) => f(ctx),
+                _ => unreachable!(),
+            }
+        }))
+    }
+}
+
+// {imap:f:items;} is a lazy map, calling f on each item only when foreach
+// gets to it, so nothing is kept but the item being worked on
+pub fn imap_func(_ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    if args.len() != 2 {
+        return throw_string!("<imap:expected 2 args, got {}>", args.len());
+    }
+    let func = Gc::clone(&args[0]);
+    let mut next = iter_items(&args[1], "imap")?;
+    Ok(new_value(VarValues::Iterator(Box::new(move |ctx| {
+        match next(ctx)? {
+            Some(item) => Ok(Some(ctx.call_value(&func, vec![item])?)),
+            None => Ok(None),
+        }
+    }))))
+}
+
+pub fn in_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    if args.len() != 2 {
+        return throw_string!("<in:expected 2 args, got {}>", args.len());
+    }
+    let ret = match &*borrow_val(&args[1])? {
+        VarValues::List(vals) => {
+            let mut found = false;
+            for v in vals.iter() {
+                if test_equality(&args[0], v)? {
+                    found = true;
+                    break;
+                }
+            }
+            found
+        },
+        VarValues::Map(vals) => {
+            vals.contains_key(&borrow_val(&args[0])?.to_string())
+        },
+        VarValues::Set(vals) => {
+            vals.contains(&borrow_val(&args[0])?.to_string())
+        },
+        v @ VarValues::Str(_) |
+        v @ VarValues::AstStr(_, _) => {
+            v.to_string().contains(&borrow_val(&args[0])?.to_string())
+        },
+        _ => {
+            return throw_string!("<in:unsupported container>");
+        },
+    };
+    Ok(ctx.new_num(if ret {1.0} else {0.0}))
+}
+
+// take keeps the items that drop removes, and vice versa
+// a negative count is measured from the end, and a count past either end is
+// clamped to it, so {take:l:-2;} gives the last 2 items and {drop:l:-2;} the rest
+fn split_list(args: &[Gc<VarValues>], func_name: &str, take: bool) -> LangResult<Gc<VarValues>> {
+    if args.len() != 2 {
+        return throw_string!("<{}:expected 2 args, got {}>", func_name, args.len());
+    }
+    let mut front = match &*borrow_val(&args[0])? {
+        VarValues::List(vals) => vals.to_vec(),
+        _ => {
+            return throw_string!("<{}:expected list>", func_name);
+        }
+    };
+    let count = val_to_f64(&args[1], func_name)?;
+    if count.is_nan() || count.fract() != 0.0 {
+        return throw_string!("<{}:invalid count>", func_name);
+    }
+    let len = front.len() as f64;
+    let from_end = count < 0.0;
+    let at = match from_end {
+        true => (len + count).max(0.0),
+        false => count.min(len),
+    };
+    let back = front.split_off(at as usize);
+    let ret = if take != from_end {front} else {back};
+    Ok(new_value(VarValues::List(Rc::new(ret))))
+}
+
+pub fn take_func(_ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    split_list(&args, "take", true)
+}
+
+pub fn drop_func(_ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    split_list(&args, "drop", false)
+}
+
+// {at:list:i;} indexes like list[i] does, but an index past either end
+// gives the item at that end, or the default if one is given
+pub fn at_func(_ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    if args.len() != 2 && args.len() != 3 {
+        return throw_string!("<at:expected 2-3 args, got {}>", args.len());
+    }
+    let items: Vec<Gc<VarValues>> = match &*borrow_val(&args[0])? {
+        VarValues::List(vals) => vals.to_vec(),
+        VarValues::Str(_) | VarValues::AstStr(..) => {
+            let s = borrow_val(&args[0])?.to_string();
+            s.chars().map(|c| new_value(VarValues::Str(c.to_string()))).collect()
+        },
+        _ => {
+            return throw_string!("<at:expected list or string>");
+        }
+    };
+    let index = val_to_f64(&args[1], "at")?;
+    if index.is_nan() || index.fract() != 0.0 {
+        return throw_string!("<at:invalid index>");
+    }
+    let index = wrap_index(index, items.len());
+    if index >= 0.0 && index < items.len() as f64 {
+        return Ok(items[index as usize]);
+    }
+    match args.get(2) {
+        Some(default) => Ok(*default),
+        None if items.is_empty() => throw_string!("<at:empty container>"),
+        None if index < 0.0 => Ok(items[0]),
+        None => Ok(items[items.len()-1]),
+    }
+}
+
+// {getor:container:index:default;} gives the default for any index that
+// doesn't pick out an item, whether it's out of range, not a whole number,
+// or a key the map doesn't have
+pub fn getor_func(_ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    if args.len() != 3 {
+        return throw_string!("<getor:expected 3 args, got {}>", args.len());
+    }
+    let position = |len: usize| -> LangResult<Option<usize>> {
+        let index = match &*borrow_val(&args[1])? {
+            VarValues::Num(n) |
+            VarValues::AstStr(_, Some(n)) => *n,
+            VarValues::Str(s) => match string_to_f64(s) {
+                Some(n) => n,
+                None => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+        if index.fract() != 0.0 {
+            return Ok(None);
+        }
+        let index = wrap_index(index, len);
+        Ok(if index >= 0.0 && index < len as f64 {Some(index as usize)} else {None})
+    };
+    let found = match &*borrow_val(&args[0])? {
+        VarValues::List(vals) => position(vals.len())?.map(|i| vals[i]),
+        VarValues::Map(vals) => vals.get(&borrow_val(&args[1])?.to_string()).copied(),
+        v @ VarValues::Str(_) |
+        v @ VarValues::AstStr(..) => {
+            let chars: Vec<char> = v.to_string().chars().collect();
+            position(chars.len())?.map(|i| new_value(VarValues::Str(chars[i].to_string())))
+        },
+        _ => {
+            return throw_string!("<getor:expected list, map or string>");
+        }
+    };
+    Ok(found.unwrap_or(args[2]))
+}
+
+fn batch_args(args: &[Gc<VarValues>], func_name: &str) -> LangResult<(Vec<Gc<VarValues>>, usize)> {
+    if args.len() != 2 {
+        return throw_string!("<{}:expected 2 args, got {}>", func_name, args.len());
+    }
+    let items = match &*borrow_val(&args[0])? {
+        VarValues::List(vals) => vals.to_vec(),
+        _ => {
+            return throw_string!("<{}:expected list>", func_name);
+        }
+    };
+    let size = val_to_f64(&args[1], func_name)?;
+    if size <= 0.0 {
+        return throw_string!("<{}:non-positive size>", func_name);
+    }
+    if size.is_nan() || size.fract() != 0.0 {
+        return throw_string!("<{}:invalid size>", func_name);
+    }
+    // a size past the end of the list behaves the same as the list's length
+    Ok((items, size.min(usize::MAX as f64) as usize))
+}
+
+pub fn chunk_func(_ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    let (items, size) = batch_args(&args, "chunk")?;
+    let chunks = items.chunks(size)
+        .map(|chunk| new_value(VarValues::List(Rc::new(chunk.to_vec()))))
+        .collect();
+    Ok(new_value(VarValues::List(Rc::new(chunks))))
+}
+
+pub fn window_func(_ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    let (items, size) = batch_args(&args, "window")?;
+    let windows = items.windows(size)
+        .map(|window| new_value(VarValues::List(Rc::new(window.to_vec()))))
+        .collect();
+    Ok(new_value(VarValues::List(Rc::new(windows))))
+}
+
+pub fn choice_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    if args.len() != 1 {
+        return throw_string!("<choice:expected 1 arg, got {}>", args.len());
+    }
+    let len = match &*borrow_val(&args[0])? {
+        VarValues::List(vals) if vals.is_empty() => {
+            return throw_string!("<choice:empty list>");
+        },
+        VarValues::List(vals) => vals.len(),
+        _ => {
+            return throw_string!("<choice:expected list>");
+        }
+    };
+    let i = ctx.random_below(len);
+    match &*borrow_val(&args[0])? {
+        VarValues::List(vals) => Ok(Gc::clone(&vals[i])),
+        _ => unreachable!(),
+    }
+}
+
+pub fn sample_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    if args.len() != 2 {
+        return throw_string!("<sample:expected 2 args, got {}>", args.len());
+    }
+    let mut items = match &*borrow_val(&args[0])? {
+        VarValues::List(vals) => vals.to_vec(),
+        _ => {
+            return throw_string!("<sample:expected list>");
+        }
+    };
+    let count = val_to_f64(&args[1], "sample")?;
+    if count.is_nan() || count < 0.0 || count.fract() != 0.0 {
+        return throw_string!("<sample:invalid count>");
+    }
+    if count > items.len() as f64 {
+        return throw_string!("<sample:count exceeds length>");
+    }
+    // a partial shuffle, so each item can only be picked once
+    let count = count as usize;
+    for i in 0..count {
+        let j = i + ctx.random_below(items.len() - i);
+        items.swap(i, j);
+    }
+    items.truncate(count);
+    Ok(new_value(VarValues::List(Rc::new(items))))
+}
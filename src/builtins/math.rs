@@ -30,9 +30,16 @@ pub(crate) fn val_to_f64(val: &Gc<VarValues>, func_name: &str) -> LangResult<f64
     }
 }
 
+// the largest integer that f64 can hold exactly, along with every integer below it
+pub(crate) const MAX_SAFE_INT: f64 = 9_007_199_254_740_991.0;
+
+pub(crate) fn is_safe_int(n: f64) -> bool {
+    n.fract() == 0.0 && n.abs() <= MAX_SAFE_INT
+}
+
 macro_rules! math_func {
-    ($func_name:ident, $lang_name:expr, $args_name:ident, $test:expr, $arg_count:expr, $op:tt) => {
-        pub fn $func_name(_ctx: &mut Context, $args_name: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    ($func_name:ident, $lang_name:expr, $args_name:ident, $test:expr, $arg_count:expr, $op:tt, $int_op:expr) => {
+        pub fn $func_name(ctx: &mut Context, $args_name: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
             if $test {
                 return throw_string!(concat!("<", $lang_name, ":expected ", $arg_count, " args, got {}>"), $args_name.len());
             }
@@ -40,16 +47,54 @@ macro_rules! math_func {
             let mut ret = val_to_f64(&$args_name[0], $lang_name)?;
 
             for arg in &$args_name[1..] {
-                ret = ret $op val_to_f64(arg, $lang_name)?;
+                let val = val_to_f64(arg, $lang_name)?;
+                // only integer operands are checked, as fractions are inexact anyway
+                let checked = $int_op && ctx.int_checks && ret.fract() == 0.0 && val.fract() == 0.0;
+                ret = ret $op val;
+                if checked && !is_safe_int(ret) {
+                    return throw_string!(concat!("<", $lang_name, ":integer overflow>"));
+                }
             }
 
-            Ok(new_value(VarValues::Num(ret)))
+            Ok(ctx.new_num(ret))
         }
     }
 }
 
-math_func!(add_func, "add", args, args.len() < 2, "2+", +);
-math_func!(sub_func, "sub", args, args.len() != 2, "2", -);
-math_func!(mul_func, "mul", args, args.len() < 2, "2+", *);
-math_func!(fdiv_func, "fdiv", args, args.len() != 2, "2", /);
-math_func!(mod_func, "mod", args, args.len() != 2, "2", %);
\ No newline at end of file
+math_func!(add_func, "add", args, args.len() < 2, "2+", +, true);
+math_func!(sub_func, "sub", args, args.len() != 2, "2", -, true);
+math_func!(mul_func, "mul", args, args.len() < 2, "2+", *, true);
+math_func!(fdiv_func, "fdiv", args, args.len() != 2, "2", /, false);
+math_func!(mod_func, "mod", args, args.len() != 2, "2", %, false);
+
+// floored rather than truncated, so the result takes the divisor's sign
+// and {modfloor:-1:3;} is 2 where mod gives -1
+pub fn modfloor_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    if args.len() != 2 {
+        return throw_string!("<modfloor:expected 2 args, got {}>", args.len());
+    }
+    let val = val_to_f64(&args[0], "modfloor")?;
+    let divisor = val_to_f64(&args[1], "modfloor")?;
+    let mut ret = val % divisor;
+    if ret == 0.0 {
+        // % leaves -0 for a negative dividend, which would print as such
+        ret = 0.0;
+    } else if (ret < 0.0) != (divisor < 0.0) {
+        ret += divisor;
+    }
+    Ok(ctx.new_num(ret))
+}
+
+pub fn maxint_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    if !args.is_empty() {
+        return throw_string!("<maxint:expected 0 args, got {}>", args.len());
+    }
+    Ok(ctx.new_num(MAX_SAFE_INT))
+}
+
+pub fn minint_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    if !args.is_empty() {
+        return throw_string!("<minint:expected 0 args, got {}>", args.len());
+    }
+    Ok(ctx.new_num(-MAX_SAFE_INT))
+}
\ No newline at end of file
@@ -1,26 +1,39 @@
 use std::collections::HashMap;
-use crate::lang_core::interp::{VarValues, VarRefType, new_value};
+use crate::lang_core::interp::{VarValues, VarRefType, Gc, new_value};
 
 pub(crate) mod boolean;
+pub(crate) mod csv;
+pub(crate) mod date;
+pub mod debug;
+pub(crate) mod encoding;
+pub(crate) mod func;
+pub(crate) mod import;
+pub(crate) mod list;
 pub(crate) mod math;
+pub(crate) mod stats;
+pub(crate) mod string;
 
 macro_rules! add_func {
     ($vars:expr, $func:expr, $($names:expr),+) => {
         {
-            let val = VarRefType::Value(new_value(VarValues::RustFunc($func)));
+            let val = new_value(VarValues::RustFunc($func));
             add_func!(__impl $vars, val, $($names),+);
         }
     };
     (__impl $vars:expr, $func:expr, $name:expr, $($names:expr),+) => {
-        $vars.insert($name.to_string(), Gc::clone(&$func));
+        $vars.insert($name.to_string(), VarRefType::Value(Gc::clone(&$func)));
         add_func!(__impl $vars, $func, $($names),+);
     };
     (__impl $vars:expr, $func:expr, $name:expr) => {
-        $vars.insert($name.to_string(), $func);
+        $vars.insert($name.to_string(), VarRefType::Value($func));
     };
 }
 
 pub fn register_builtins(vars: &mut HashMap<String, VarRefType>) {
+    // plain values rather than functions, so they read as {true} and {false}.
+    // a function is always truthy, so {false} couldn't be one
+    vars.insert("true".to_string(), VarRefType::Value(new_value(VarValues::Num(1.0))));
+    vars.insert("false".to_string(), VarRefType::Value(new_value(VarValues::Num(0.0))));
     add_func!(vars, boolean::not_func, "not");
     add_func!(vars, boolean::and_func, "and");
     add_func!(vars, boolean::or_func, "or");
@@ -30,9 +43,66 @@ pub fn register_builtins(vars: &mut HashMap<String, VarRefType>) {
     add_func!(vars, boolean::gt_func, "gt");
     add_func!(vars, boolean::le_func, "le");
     add_func!(vars, boolean::ge_func, "ge");
+    add_func!(vars, boolean::between_func, "between");
+    add_func!(vars, boolean::hash_func, "hash");
+    add_func!(vars, boolean::asserteq_func, "asserteq");
+    add_func!(vars, boolean::ensure_func, "ensure");
     add_func!(vars, math::add_func, "add");
     add_func!(vars, math::sub_func, "sub");
     add_func!(vars, math::mul_func, "mul");
     add_func!(vars, math::fdiv_func, "fdiv");
     add_func!(vars, math::mod_func, "mod");
+    add_func!(vars, math::modfloor_func, "modfloor");
+    add_func!(vars, math::maxint_func, "maxint");
+    add_func!(vars, math::minint_func, "minint");
+    add_func!(vars, string::zfill_func, "zfill");
+    add_func!(vars, string::pretty_func, "pretty");
+    add_func!(vars, string::strcat_func, "strcat");
+    add_func!(vars, string::bytes_func, "bytes");
+    add_func!(vars, string::frombytes_func, "frombytes");
+    add_func!(vars, string::chars_func, "chars");
+    add_func!(vars, string::lines_func, "lines");
+    add_func!(vars, string::htmlescape_func, "htmlescape");
+    add_func!(vars, string::htmlunescape_func, "htmlunescape");
+    add_func!(vars, string::raw_func, "raw");
+    add_func!(vars, csv::fromcsv_func, "fromcsv");
+    add_func!(vars, csv::tocsv_func, "tocsv");
+    add_func!(vars, encoding::urlencode_func, "urlencode");
+    add_func!(vars, encoding::urldecode_func, "urldecode");
+    add_func!(vars, date::strftime_func, "strftime");
+    add_func!(vars, date::strptime_func, "strptime");
+    add_func!(vars, list::range_func, "range");
+    add_func!(vars, list::irange_func, "irange");
+    add_func!(vars, list::imap_func, "imap");
+    add_func!(vars, list::any_func, "any");
+    add_func!(vars, list::all_func, "all");
+    add_func!(vars, list::fold_func, "fold");
+    add_func!(vars, list::minby_func, "minby");
+    add_func!(vars, list::maxby_func, "maxby");
+    add_func!(vars, list::groupby_func, "groupby");
+    add_func!(vars, list::counter_func, "counter");
+    add_func!(vars, stats::sum_func, "sum");
+    add_func!(vars, stats::product_func, "product");
+    add_func!(vars, stats::average_func, "average", "mean");
+    add_func!(vars, stats::median_func, "median");
+    add_func!(vars, stats::stddev_func, "stddev");
+    add_func!(vars, list::repeat_func, "repeat");
+    add_func!(vars, list::generate_func, "generate");
+    add_func!(vars, list::in_func, "in");
+    add_func!(vars, list::take_func, "take");
+    add_func!(vars, list::drop_func, "drop");
+    add_func!(vars, list::at_func, "at");
+    add_func!(vars, list::getor_func, "getor");
+    add_func!(vars, list::chunk_func, "chunk");
+    add_func!(vars, list::window_func, "window");
+    add_func!(vars, list::choice_func, "choice");
+    add_func!(vars, list::sample_func, "sample");
+    add_func!(vars, list::copy_func, "copy");
+    // "set" itself is taken by variable assignment
+    add_func!(vars, list::set_func, "newset");
+    add_func!(vars, import::import_func, "import");
+    add_func!(vars, debug::inspect_func, "inspect");
+    add_func!(vars, debug::type_func, "type");
+    add_func!(vars, debug::depth_func, "depth");
+    add_func!(vars, func::curry_func, "curry");
 }
\ No newline at end of file
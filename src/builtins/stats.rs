@@ -0,0 +1,75 @@
+use crate::throw_string;
+use crate::lang_core::interp::{
+    LangResult,
+    LangError,
+    VarValues,
+    Context,
+    Gc,
+    new_value,
+    borrow_val
+};
+use crate::builtins::math::val_to_f64;
+
+fn list_nums(args: &[Gc<VarValues>], func_name: &str) -> LangResult<Vec<f64>> {
+    if args.len() != 1 {
+        return throw_string!("<{}:expected 1 arg, got {}>", func_name, args.len());
+    }
+    match &*borrow_val(&args[0])? {
+        VarValues::List(vals) => {
+            vals.iter().map(|v| val_to_f64(v, func_name)).collect()
+        },
+        _ => {
+            throw_string!("<{}:expected list>", func_name)
+        }
+    }
+}
+
+// anything without a sensible answer for no numbers at all throws instead
+fn nonempty_nums(args: &[Gc<VarValues>], func_name: &str) -> LangResult<Vec<f64>> {
+    let nums = list_nums(args, func_name)?;
+    if nums.is_empty() {
+        return throw_string!("<{}:empty list>", func_name);
+    }
+    Ok(nums)
+}
+
+fn mean(nums: &[f64]) -> f64 {
+    nums.iter().sum::<f64>() / nums.len() as f64
+}
+
+pub fn sum_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    let nums = list_nums(&args, "sum")?;
+    // fold rather than sum, which starts from -0 and would print it
+    Ok(ctx.new_num(nums.iter().fold(0.0, |acc, n| acc + n)))
+}
+
+pub fn product_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    let nums = list_nums(&args, "product")?;
+    Ok(ctx.new_num(nums.iter().product()))
+}
+
+pub fn average_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    let nums = nonempty_nums(&args, "average")?;
+    Ok(ctx.new_num(mean(&nums)))
+}
+
+// the middle number once sorted, or halfway between the middle two
+pub fn median_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    let mut nums = nonempty_nums(&args, "median")?;
+    nums.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let mid = nums.len() / 2;
+    let median = if nums.len() % 2 == 0 {
+        (nums[mid - 1] + nums[mid]) / 2.0
+    } else {
+        nums[mid]
+    };
+    Ok(ctx.new_num(median))
+}
+
+// the population standard deviation, treating the list as everything there is
+pub fn stddev_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    let nums = nonempty_nums(&args, "stddev")?;
+    let mean = mean(&nums);
+    let variance = nums.iter().map(|n| (n - mean) * (n - mean)).sum::<f64>() / nums.len() as f64;
+    Ok(ctx.new_num(variance.sqrt()))
+}
@@ -0,0 +1,313 @@
+use std::io::BufRead;
+use std::rc::Rc;
+use crate::throw_string;
+use crate::lang_core::interp::{
+    LangResult,
+    LangError,
+    VarValues,
+    Context,
+    Gc,
+    new_value,
+    borrow_val
+};
+use crate::builtins::math::val_to_f64;
+
+// anything wider than this is almost certainly a mistake, and would just
+// exhaust memory trying to build the padding
+const MAX_ZFILL_WIDTH: f64 = 1_048_576.0;
+
+pub fn zfill_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    if args.len() != 2 {
+        return throw_string!("<zfill:expected 2 args, got {}>", args.len());
+    }
+    let s = borrow_val(&args[0])?.to_string();
+    let width = val_to_f64(&args[1], "zfill")?;
+    if !width.is_finite() || width.fract() != 0.0 || width > MAX_ZFILL_WIDTH {
+        return throw_string!("<zfill:invalid width>");
+    }
+
+    let len = s.chars().count();
+    if width <= len as f64 {
+        return Ok(ctx.new_str(s));
+    }
+    let fill = width as usize - len;
+
+    // the sign stays in front of the padding, so -7 becomes -007
+    let (sign, digits) = match s.chars().next() {
+        Some(c @ '-') | Some(c @ '+') => (Some(c), &s[1..]),
+        _ => (None, &s[..]),
+    };
+    let mut ret = String::with_capacity(s.len() + fill);
+    if let Some(c) = sign {
+        ret.push(c);
+    }
+    for _ in 0..fill {
+        ret.push('0');
+    }
+    ret.push_str(digits);
+    Ok(ctx.new_str(ret))
+}
+
+// joins the string forms of its args, giving a str even where a block's
+// output would have been kept as a single value, like {x} on its own
+pub fn strcat_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    let mut ret = String::new();
+    for arg in &args {
+        ret.push_str(&borrow_val(arg)?.to_string());
+    }
+    Ok(ctx.new_str(ret))
+}
+
+// the utf-8 encoding of a value's string form, as a list of numbers
+pub fn bytes_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    if args.len() != 1 {
+        return throw_string!("<bytes:expected 1 arg, got {}>", args.len());
+    }
+    let s = borrow_val(&args[0])?.to_string();
+    let vals = s.bytes()
+        .map(|b| ctx.new_num(b as f64))
+        .collect();
+    Ok(new_value(VarValues::List(Rc::new(vals))))
+}
+
+pub fn frombytes_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    if args.len() != 1 {
+        return throw_string!("<frombytes:expected 1 arg, got {}>", args.len());
+    }
+    let items = match &*borrow_val(&args[0])? {
+        VarValues::List(vals) => vals.to_vec(),
+        _ => {
+            return throw_string!("<frombytes:expected list>");
+        }
+    };
+    let mut bytes = Vec::with_capacity(items.len());
+    for item in &items {
+        let b = val_to_f64(item, "frombytes")?;
+        if b.fract() != 0.0 || !(0.0..=255.0).contains(&b) {
+            return throw_string!("<frombytes:invalid byte>");
+        }
+        bytes.push(b as u8);
+    }
+    match String::from_utf8(bytes) {
+        Ok(s) => Ok(ctx.new_str(s)),
+        Err(_) => throw_string!("<frombytes:invalid utf-8>"),
+    }
+}
+
+// indexing a string walks it from the start to find the nth char, so a loop
+// indexing every char is quadratic; this splits it up once instead
+pub fn chars_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    if args.len() != 1 {
+        return throw_string!("<chars:expected 1 arg, got {}>", args.len());
+    }
+    let s = borrow_val(&args[0])?.to_string();
+    let vals = s.chars()
+        .map(|c| ctx.new_str(c.to_string()))
+        .collect();
+    Ok(new_value(VarValues::List(Rc::new(vals))))
+}
+
+// the characters that can break out of text or an attribute value in html
+pub(crate) fn html_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+pub fn htmlescape_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    if args.len() != 1 {
+        return throw_string!("<htmlescape:expected 1 arg, got {}>", args.len());
+    }
+    let s = borrow_val(&args[0])?.to_string();
+    Ok(ctx.new_str(html_escape(&s)))
+}
+
+fn html_entity(name: &str) -> Option<char> {
+    match name {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        _ => {
+            let code = match name.strip_prefix("#x").or_else(|| name.strip_prefix("#X")) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => name.strip_prefix('#')?.parse().ok()?,
+            };
+            std::char::from_u32(code)
+        },
+    }
+}
+
+// marks text as already escaped, so --escape leaves it alone when output
+pub fn raw_func(_ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    if args.len() != 1 {
+        return throw_string!("<raw:expected 1 arg, got {}>", args.len());
+    }
+    let s = borrow_val(&args[0])?.to_string();
+    Ok(new_value(VarValues::Raw(s)))
+}
+
+// undoes htmlescape, along with any numeric entity, while anything that
+// isn't a known entity is left as it is
+pub fn htmlunescape_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    if args.len() != 1 {
+        return throw_string!("<htmlunescape:expected 1 arg, got {}>", args.len());
+    }
+    let s = borrow_val(&args[0])?.to_string();
+    let mut out = String::with_capacity(s.len());
+    let mut rest = &s[..];
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest.find(';').and_then(|end| Some((html_entity(&rest[1..end])?, end)));
+        match entity {
+            Some((c, end)) => {
+                out.push(c);
+                rest = &rest[end+1..];
+            },
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            },
+        }
+    }
+    out.push_str(rest);
+    Ok(ctx.new_str(out))
+}
+
+// {lines;} goes through stdin a line at a time as foreach asks for it,
+// so input of any size can be worked through without reading it all in
+pub fn lines_func(_ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    if !args.is_empty() {
+        return throw_string!("<lines:expected 0 args, got {}>", args.len());
+    }
+    Ok(new_value(VarValues::Iterator(Box::new(|ctx| {
+        let mut line = String::new();
+        match std::io::stdin().lock().read_line(&mut line) {
+            Ok(0) => Ok(None),
+            Ok(_) => {
+                if line.ends_with('\n') {
+                    line.pop();
+                    if line.ends_with('\r') {
+                        line.pop();
+                    }
+                }
+                Ok(Some(ctx.new_str(line)))
+            },
+            Err(_) => throw_string!("<lines:could not read stdin>"),
+        }
+    }))))
+}
+
+// anything deeper than this is more than anyone could read anyway
+const MAX_PRETTY_INDENT: f64 = 16.0;
+
+fn push_indent(out: &mut String, width: usize) {
+    for _ in 0..width {
+        out.push(' ');
+    }
+}
+
+// seen holds the lists and maps currently being rendered, so one that
+// contains itself is shown as [...] instead of recursing forever
+fn pretty_value(val: &Gc<VarValues>, indent: usize, depth: usize, seen: &mut Vec<*const VarValues>, out: &mut String) -> LangResult<()> {
+    let val_ref = borrow_val(val)?;
+    let ptr = &*val_ref as *const VarValues;
+    match &*val_ref {
+        VarValues::List(vals) if vals.is_empty() => {
+            out.push_str("[]");
+        },
+        VarValues::Map(vals) if vals.is_empty() => {
+            out.push_str("{}");
+        },
+        VarValues::List(_) if seen.contains(&ptr) => {
+            out.push_str("[...]");
+        },
+        VarValues::Map(_) if seen.contains(&ptr) => {
+            out.push_str("{...}");
+        },
+        VarValues::List(vals) => {
+            seen.push(ptr);
+            out.push_str("[\n");
+            for (i, v) in vals.iter().enumerate() {
+                push_indent(out, indent * (depth + 1));
+                pretty_value(v, indent, depth + 1, seen, out)?;
+                if i + 1 < vals.len() {
+                    out.push(',');
+                }
+                out.push('\n');
+            }
+            push_indent(out, indent * depth);
+            out.push(']');
+            seen.pop();
+        },
+        VarValues::Map(vals) => {
+            seen.push(ptr);
+            // sorted, so the same map always renders the same way
+            let mut keys: Vec<&String> = vals.keys().collect();
+            keys.sort();
+            out.push_str("{\n");
+            for (i, key) in keys.iter().enumerate() {
+                push_indent(out, indent * (depth + 1));
+                out.push_str(key);
+                out.push_str(": ");
+                pretty_value(&vals[*key], indent, depth + 1, seen, out)?;
+                if i + 1 < keys.len() {
+                    out.push(',');
+                }
+                out.push('\n');
+            }
+            push_indent(out, indent * depth);
+            out.push('}');
+            seen.pop();
+        },
+        VarValues::Set(vals) => {
+            // items are plain strings, so a set always fits on one line
+            let mut items: Vec<&String> = vals.iter().collect();
+            items.sort();
+            out.push_str("set(");
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                out.push_str(item);
+            }
+            out.push(')');
+        },
+        v => {
+            out.push_str(&v.to_string());
+        },
+    }
+    Ok(())
+}
+
+pub(crate) fn pretty_string(val: &Gc<VarValues>, indent: usize) -> LangResult<String> {
+    let mut out = String::new();
+    pretty_value(val, indent, 0, &mut Vec::new(), &mut out)?;
+    Ok(out)
+}
+
+pub fn pretty_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    if args.is_empty() || args.len() > 2 {
+        return throw_string!("<pretty:expected 1-2 args, got {}>", args.len());
+    }
+    let indent = match args.get(1) {
+        Some(arg) => val_to_f64(arg, "pretty")?,
+        None => 2.0,
+    };
+    if !(0.0..=MAX_PRETTY_INDENT).contains(&indent) || indent.fract() != 0.0 {
+        return throw_string!("<pretty:invalid indent>");
+    }
+
+    Ok(ctx.new_str(pretty_string(&args[0], indent as usize)?))
+}
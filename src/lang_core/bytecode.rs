@@ -1,26 +1,40 @@
 #![allow(unreachable_patterns)]
 
 use crate::lang_core::parse::{AST, VarAccess, Accessor};
+use crate::lang_core::optimise;
 use std::mem;
+use std::rc::Rc;
+use std::fmt;
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "bytecode-files", derive(serde::Serialize, serde::Deserialize))]
 pub enum Instruction {
     PUSHSTR(String),
-    PUSHASTSTR(String, Option<f64>),
+    PUSHASTSTR(Rc<str>, Option<f64>),
     PUSHNIL,
     PUSHNUM(f64),
     OUTPUTSTR(String, Option<f64>),
     OUTPUTVAL,
     IFFALSE(usize),
+    IFNOTTYPE(String, usize),
     GOTO(usize),
     CONCAT(usize),
     DROP(usize),
-    CREATEFUNC(Vec<String>, usize, usize),
+    DUP(usize),
+    SWAP,
+    CREATEFUNC(Option<String>, Vec<String>, bool, usize, usize),
     CALLFUNC(usize, bool),
+    // the same as CALLFUNC, for the args marked true being lists to spread
+    CALLSPREAD(Vec<bool>, bool),
     CREATELIST(usize),
     CREATEMAP(usize),
+    // replaces a list with its items, throwing if it doesn't have that many
+    UNPACKLIST(usize),
+    // replaces a map with what it has under each key, throwing on any it doesn't
+    UNPACKMAP(Vec<String>),
     GETVAR(String),
     GETINDEX,
+    GETSLICE,
     GETATTR,
     SETVAR(String),
     SETINDEX,
@@ -29,6 +43,8 @@ pub enum Instruction {
     DELINDEX,
     DELATTR,
     SETNONLOCAL(String),
+    PUSHSCOPE(Vec<String>),
+    POPSCOPE(usize),
     WHILESTART,
     FORSTART(String),
     FORTEST(usize),
@@ -37,13 +53,37 @@ pub enum Instruction {
     FOREACHITER(usize),
     LOOPINCR,
     LOOPEND(bool),
+    LOOPENDLIST,
     STARTCATCH(usize),
     ENDCATCH,
     UNWINDCATCH(usize),
     THROWVAL,
+    UNWRAPCATCH,
+    CATCHKIND,
+    STARTTRANSFORM(OutputTransform, bool),
     END,
 }
 
+// what a transform block does to everything output inside it
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "bytecode-files", derive(serde::Serialize, serde::Deserialize))]
+pub enum OutputTransform {
+    Upper,
+    Lower,
+    Trim,
+}
+
+impl OutputTransform {
+    pub fn from_name(name: &str) -> Option<OutputTransform> {
+        match name {
+            "upper" => Some(OutputTransform::Upper),
+            "lower" => Some(OutputTransform::Lower),
+            "trim" => Some(OutputTransform::Trim),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug)]
 enum ValStatus {
     Temp,
@@ -55,7 +95,11 @@ struct LoopJumps {
     breaks: Vec<usize>,
     continues: Vec<usize>,
     catch_count: usize,
+    // how many lets the loop body is inside of, which a break or continue
+    // has to leave the scopes of
+    scope_count: usize,
     val_counts: Vec<(ValStatus, usize, usize)>,
+    cleanups: Vec<(String, Vec<AST>)>,
 }
 
 #[derive(Debug)]
@@ -64,6 +108,8 @@ struct CompilerCtx {
     funcs: Vec<(usize, Vec<Instruction>)>,
     current_loop: Option<LoopJumps>,
     in_function: bool,
+    // how many lets are being compiled, one inside the other
+    let_depth: usize,
 }
 
 impl CompilerCtx {
@@ -87,41 +133,136 @@ impl CompilerCtx {
             cur_loop.catch_count -= 1;
         }
     }
+    #[inline]
+    fn inc_scope_count(&mut self) {
+        self.let_depth += 1;
+        if let Some(cur_loop) = &mut self.current_loop {
+            cur_loop.scope_count += 1;
+        }
+    }
+    #[inline]
+    fn dec_scope_count(&mut self) {
+        self.let_depth -= 1;
+        if let Some(cur_loop) = &mut self.current_loop {
+            cur_loop.scope_count -= 1;
+        }
+    }
 }
 
 #[derive(Debug)]
 enum InternalASTErrors {
     LoopJumpCutoff,
-    InvalidArgCount(String, usize),
-    NonlocalInGlobalScope,
-    InvalidIdentifier(String),
-    ContinueOutsideOfLoop,
-    BreakOutsideOfLoop,
-    CannotSetFunctionCall,
-    EmptySetCall,
-    CannotDelFunctionCall,
-    EmptyDelCall
+    InvalidArgCount(usize, String, usize),
+    NonlocalInGlobalScope(usize),
+    InvalidIdentifier(usize, String),
+    ContinueOutsideOfLoop(usize),
+    BreakOutsideOfLoop(usize),
+    CannotSetFunctionCall(usize),
+    EmptySetCall(usize),
+    CannotDelFunctionCall(usize),
+    EmptyDelCall(usize),
+    CannotSetSlice(usize),
+    CannotDelSlice(usize),
+    UnknownTransform(usize, String),
+    UnknownType(usize, String)
 }
 
+// each starts with the line of the source it was found on
 #[derive(Debug)]
 pub enum ASTErrors {
-    InvalidArgCount(String, usize),
-    NonlocalInGlobalScope,
-    InvalidIdentifier(String),
-    ContinueOutsideOfLoop,
-    BreakOutsideOfLoop,
-    CannotSetFunctionCall,
-    EmptySetCall,
-    CannotDelFunctionCall,
-    EmptyDelCall
+    InvalidArgCount(usize, String, usize),
+    NonlocalInGlobalScope(usize),
+    InvalidIdentifier(usize, String),
+    ContinueOutsideOfLoop(usize),
+    BreakOutsideOfLoop(usize),
+    CannotSetFunctionCall(usize),
+    EmptySetCall(usize),
+    CannotDelFunctionCall(usize),
+    EmptyDelCall(usize),
+    CannotSetSlice(usize),
+    CannotDelSlice(usize),
+    UnknownTransform(usize, String),
+    UnknownType(usize, String)
 }
 
+impl fmt::Display for ASTErrors {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let line = match self {
+            ASTErrors::InvalidArgCount(line, _, _) |
+            ASTErrors::InvalidIdentifier(line, _) |
+            ASTErrors::NonlocalInGlobalScope(line) |
+            ASTErrors::ContinueOutsideOfLoop(line) |
+            ASTErrors::BreakOutsideOfLoop(line) |
+            ASTErrors::CannotSetFunctionCall(line) |
+            ASTErrors::EmptySetCall(line) |
+            ASTErrors::CannotDelFunctionCall(line) |
+            ASTErrors::EmptyDelCall(line) |
+            ASTErrors::CannotSetSlice(line) |
+            ASTErrors::CannotDelSlice(line) |
+            ASTErrors::UnknownTransform(line, _) |
+            ASTErrors::UnknownType(line, _) => line,
+        };
+        write!(fmt, "line {}: ", line)?;
+        match self {
+            ASTErrors::InvalidArgCount(_, name, n) => {
+                write!(fmt, "{} given the wrong number of args ({})", name, n)
+            },
+            ASTErrors::NonlocalInGlobalScope(_) => {
+                write!(fmt, "nonlocal used outside of a function")
+            },
+            ASTErrors::InvalidIdentifier(_, name) => {
+                write!(fmt, "{} given an invalid variable name", name)
+            },
+            ASTErrors::ContinueOutsideOfLoop(_) => {
+                write!(fmt, "continue used outside of a loop")
+            },
+            ASTErrors::BreakOutsideOfLoop(_) => {
+                write!(fmt, "break used outside of a loop")
+            },
+            ASTErrors::CannotSetFunctionCall(_) => {
+                write!(fmt, "cannot assign to the result of a function call")
+            },
+            ASTErrors::EmptySetCall(_) => {
+                write!(fmt, "set has no variable to assign to")
+            },
+            ASTErrors::CannotDelFunctionCall(_) => {
+                write!(fmt, "cannot delete the result of a function call")
+            },
+            ASTErrors::EmptyDelCall(_) => {
+                write!(fmt, "del has no variable to delete")
+            },
+            ASTErrors::CannotSetSlice(_) => {
+                write!(fmt, "cannot assign to a slice")
+            },
+            ASTErrors::CannotDelSlice(_) => {
+                write!(fmt, "cannot delete a slice")
+            },
+            ASTErrors::UnknownTransform(_, name) => {
+                write!(fmt, "unknown transform '{}', expected upper, lower or trim", name)
+            },
+            ASTErrors::UnknownType(_, name) => {
+                write!(fmt, "unknown type '{}' given to matchtype", name)
+            },
+        }
+    }
+}
+
+impl std::error::Error for ASTErrors {}
+
 fn ast_accessor_bytecode(ctx: &mut CompilerCtx, accessor: &Accessor) -> Result<(), InternalASTErrors> {
     match accessor {
         Accessor::Index(arg) => {
             ast_vec_bytecode(ctx, arg, ValStatus::Temp, false, false)?;
             ctx.prog.push(Instruction::GETINDEX);
         },
+        Accessor::Slice(parts) => {
+            // a missing step compiles to nil like any other empty part
+            for i in 0..3 {
+                let part = parts.get(i).map(|v| &v[..]).unwrap_or(&[]);
+                ast_vec_bytecode(ctx, part, ValStatus::Temp, false, false)?;
+            }
+            ctx.prog.push(Instruction::GETSLICE);
+        },
         Accessor::Attr(arg) => {
             ast_vec_bytecode(ctx, arg, ValStatus::Temp, false, false)?;
             ctx.prog.push(Instruction::GETATTR);
@@ -133,10 +274,52 @@ fn ast_accessor_bytecode(ctx: &mut CompilerCtx, accessor: &Accessor) -> Result<(
             ctx.set_block_args(1);
             ctx.prog.push(Instruction::CALLFUNC(args.len(), false));
         },
+        Accessor::SpreadCall(args) => {
+            for (_, arg) in args {
+                ast_vec_bytecode(ctx, arg, ValStatus::Temp, true, false)?;
+            }
+            ctx.set_block_args(1);
+            let spread = args.iter().map(|(spread, _)| *spread).collect();
+            ctx.prog.push(Instruction::CALLSPREAD(spread, false));
+        },
     }
     Ok(())
 }
 
+// calls the cleanup function of a with block on its resource
+fn ast_with_cleanup(ctx: &mut CompilerCtx, ident: &str, cleanup: &[AST]) -> Result<(), InternalASTErrors> {
+    ast_vec_bytecode(ctx, cleanup, ValStatus::Temp, true, false)?;
+    ctx.prog.push(Instruction::GETVAR(ident.to_owned()));
+    ctx.prog.push(Instruction::CALLFUNC(1, false));
+    ctx.prog.push(Instruction::DROP(1));
+    Ok(())
+}
+
+// the op of a setop is a function name, like the first part of a call,
+// but it can also be a block giving the function itself
+fn ast_setop_func(ctx: &mut CompilerCtx, op: &[AST]) -> Result<(), InternalASTErrors> {
+    match op {
+        [AST::String(s, _)] => {
+            ctx.prog.push(Instruction::GETVAR(s.to_owned()));
+        },
+        _ => {
+            ast_vec_bytecode(ctx, op, ValStatus::Temp, false, false)?;
+        },
+    }
+    Ok(())
+}
+
+// a break or continue leaves every with block it's inside of in the loop, so
+// once it has unwound out of their catches, their cleanups are run innermost first
+fn ast_loop_cleanups(ctx: &mut CompilerCtx, cleanups: &[(String, Vec<AST>)]) -> Result<(), InternalASTErrors> {
+    let current_loop = ctx.current_loop.take();
+    let ret = cleanups.iter()
+        .rev()
+        .try_for_each(|(ident, cleanup)| ast_with_cleanup(ctx, ident, cleanup));
+    ctx.current_loop = current_loop;
+    ret
+}
+
 fn ast_var_access(ctx: &mut CompilerCtx, var: &VarAccess, direct_output: bool) -> Result<(), InternalASTErrors> {
     match &var.value[..] {
         [AST::String(s, _)] => {
@@ -153,7 +336,8 @@ fn ast_var_access(ctx: &mut CompilerCtx, var: &VarAccess, direct_output: bool) -
         ast_accessor_bytecode(ctx, accessor)?;
     }
     match (ctx.prog.last_mut(), direct_output) {
-        (Some(Instruction::CALLFUNC(_, output)), true) => {
+        (Some(Instruction::CALLFUNC(_, output)), true) |
+        (Some(Instruction::CALLSPREAD(_, output)), true) => {
             // CALLFUNC with direct output enabled automatically outputs its vals,
             // so no OUTPUTVAL instruction is needed
             *output = true;
@@ -190,16 +374,19 @@ fn ast_bytecode(ctx: &mut CompilerCtx, ast: &AST, direct_output: bool) -> Result
         AST::String(s, v) => {
             ctx.prog.push(match direct_output {
                 true => Instruction::OUTPUTSTR(s.to_owned(), *v),
-                false => Instruction::PUSHASTSTR(s.to_owned(), *v),
+                false => Instruction::PUSHASTSTR(Rc::from(&s[..]), *v),
             });
             Ok(true)
         },
         AST::Variable(var) => {
             match (&var.value[..], &var.accessors[..]) {
                 ([AST::String(s, _)], [Accessor::Call(args)]) => match &s[..] {
+                    // {if:c1:a:c2:b:...:else;} takes args in condition/branch pairs,
+                    // and an odd one left over at the end is the else branch
+                    // without one, a call where no condition holds gives nil
                     "if" => {
                         if args.len() < 2 {
-                            return Err(InternalASTErrors::InvalidArgCount(String::from("if"), args.len()));
+                            return Err(InternalASTErrors::InvalidArgCount(var.line, String::from("if"), args.len()));
                         }
                         let mut i = 0;
                         let mut end_jumps = Vec::new();
@@ -249,11 +436,75 @@ fn ast_bytecode(ctx: &mut CompilerCtx, ast: &AST, direct_output: bool) -> Result
                         }
                         Ok(true)
                     },
+                    // {matchtype:val:list:a:num:b:c;} runs only the branch for the type
+                    // of val, or the last arg if none of them match, like an if
+                    "matchtype" => {
+                        if args.len() < 3 {
+                            return Err(InternalASTErrors::InvalidArgCount(var.line, String::from("matchtype"), args.len()));
+                        }
+                        const TYPE_NAMES: &[&str] = &[
+                            "nil", "num", "str", "func", "catch", "list", "map", "set", "range", "iterator"
+                        ];
+                        ast_vec_bytecode(ctx, &args[0], ValStatus::Temp, false, false)?;
+                        let mut end_jumps = Vec::new();
+                        let mut i = 1;
+                        while i < args.len() - 1 {
+                            let name = match &args[i][..] {
+                                [AST::String(name, _)] if TYPE_NAMES.contains(&&name[..]) => name.clone(),
+                                [AST::String(name, _)] => {
+                                    return Err(InternalASTErrors::UnknownType(var.line, name.clone()));
+                                },
+                                _ => {
+                                    return Err(InternalASTErrors::UnknownType(var.line, String::new()));
+                                }
+                            };
+                            let test = ctx.prog.len();
+                            ctx.prog.push(Instruction::IFNOTTYPE(name, 0));
+                            ctx.prog.push(Instruction::DROP(1));
+                            match ast_vec_bytecode(ctx, &args[i+1], ValStatus::Returned, false, direct_output) {
+                                Ok(_) | Err(InternalASTErrors::LoopJumpCutoff) => {},
+                                Err(v) => return Err(v),
+                            }
+                            end_jumps.push(ctx.prog.len());
+                            ctx.prog.push(Instruction::GOTO(0));
+                            let current_len = ctx.prog.len();
+                            match &mut ctx.prog[test] {
+                                Instruction::IFNOTTYPE(_, p) => {
+                                    *p = current_len;
+                                }
+                                _ => unreachable!()
+                            }
+                            i += 2;
+                        }
+                        ctx.prog.push(Instruction::DROP(1));
+                        if args.len() % 2 == 1 {
+                            // no default branch given, add a nil for a placeholder
+                            ctx.prog.push(Instruction::PUSHNIL);
+                        } else {
+                            match ast_vec_bytecode(ctx, args.last().unwrap(), ValStatus::Returned, false, direct_output) {
+                                Ok(_) | Err(InternalASTErrors::LoopJumpCutoff) => {},
+                                Err(v) => return Err(v),
+                            }
+                        }
+                        let current_len = ctx.prog.len();
+                        for inst in end_jumps {
+                            match &mut ctx.prog[inst] {
+                                Instruction::GOTO(p) => {
+                                    *p = current_len;
+                                }
+                                _ => unreachable!()
+                            }
+                        }
+                        Ok(true)
+                    },
                     "lambda" => {
                         if args.len() == 0 {
-                            return Err(InternalASTErrors::InvalidArgCount(String::from("lambda"), args.len()));
+                            return Err(InternalASTErrors::InvalidArgCount(var.line, String::from("lambda"), args.len()));
+                        }
+                        if let Some(self_name) = lambda_self_name(args) {
+                            return ast_bytecode(ctx, &self_named_lambda(self_name, &args[1..], var.line), direct_output);
                         }
-                        ast_compile_function(ctx, args)?;
+                        ast_compile_function(ctx, args, None, var.line)?;
                         if direct_output {
                             ctx.prog.push(Instruction::OUTPUTVAL);
                         }
@@ -271,7 +522,7 @@ fn ast_bytecode(ctx: &mut CompilerCtx, ast: &AST, direct_output: bool) -> Result
                     },
                     "map" => {
                         if args.len() % 2 != 0 {
-                            return Err(InternalASTErrors::InvalidArgCount(String::from("map"), args.len()));
+                            return Err(InternalASTErrors::InvalidArgCount(var.line, String::from("map"), args.len()));
                         }
                         for v in args {
                             ast_vec_bytecode(ctx, v, ValStatus::Temp, true, false)?;
@@ -284,24 +535,44 @@ fn ast_bytecode(ctx: &mut CompilerCtx, ast: &AST, direct_output: bool) -> Result
                     },
                     "nonlocal" => {
                         if args.len() != 1 {
-                            return Err(InternalASTErrors::InvalidArgCount(String::from("nonlocal"), args.len()));
+                            return Err(InternalASTErrors::InvalidArgCount(var.line, String::from("nonlocal"), args.len()));
                         }
-                        if !ctx.in_function {
-                            return Err(InternalASTErrors::NonlocalInGlobalScope);
+                        // a let has a scope of its own even outside of a function
+                        if !ctx.in_function && ctx.let_depth == 0 {
+                            return Err(InternalASTErrors::NonlocalInGlobalScope(var.line));
                         }
                         match &args[0][..] {
                             [AST::String(s, _)] => {
                                 ctx.prog.push(Instruction::SETNONLOCAL(s.to_owned()));
                             },
                             _ => {
-                                return Err(InternalASTErrors::InvalidIdentifier(String::from("nonlocal")));
+                                return Err(InternalASTErrors::InvalidIdentifier(var.line, String::from("nonlocal")));
+                            }
+                        }
+                        Ok(false)
+                    },
+                    "swap" => {
+                        if args.len() != 2 {
+                            return Err(InternalASTErrors::InvalidArgCount(var.line, String::from("swap"), args.len()));
+                        }
+                        match (&args[0][..], &args[1][..]) {
+                            ([AST::String(a, _)], [AST::String(b, _)]) => {
+                                // both are read before either is set, so each
+                                // gets what the other had
+                                ctx.prog.push(Instruction::GETVAR(a.to_owned()));
+                                ctx.prog.push(Instruction::GETVAR(b.to_owned()));
+                                ctx.prog.push(Instruction::SETVAR(a.to_owned()));
+                                ctx.prog.push(Instruction::SETVAR(b.to_owned()));
+                            },
+                            _ => {
+                                return Err(InternalASTErrors::InvalidIdentifier(var.line, String::from("swap")));
                             }
                         }
                         Ok(false)
                     },
                     "throw" => {
                         if args.len() != 1 {
-                            return Err(InternalASTErrors::InvalidArgCount(String::from("throw"), args.len()));
+                            return Err(InternalASTErrors::InvalidArgCount(var.line, String::from("throw"), args.len()));
                         }
                         ast_vec_bytecode(ctx, &args[0], ValStatus::Temp, true, false)?;
                         ctx.prog.push(Instruction::THROWVAL);
@@ -309,7 +580,7 @@ fn ast_bytecode(ctx: &mut CompilerCtx, ast: &AST, direct_output: bool) -> Result
                     },
                     "catch" => {
                         if args.len() != 1 {
-                            return Err(InternalASTErrors::InvalidArgCount(String::from("catch"), args.len()));
+                            return Err(InternalASTErrors::InvalidArgCount(var.line, String::from("catch"), args.len()));
                         }
                         let startcatch_index = ctx.prog.len();
                         ctx.prog.push(Instruction::STARTCATCH(0));
@@ -336,18 +607,196 @@ fn ast_bytecode(ctx: &mut CompilerCtx, ast: &AST, direct_output: bool) -> Result
                         }
                         Ok(true)
                     },
+                    // the same as catch, except anything thrown with a different kind
+                    // carries on up past it, as if it was never caught
+                    "catchkind" => {
+                        if args.len() != 2 {
+                            return Err(InternalASTErrors::InvalidArgCount(var.line, String::from("catchkind"), args.len()));
+                        }
+                        ast_vec_bytecode(ctx, &args[0], ValStatus::Temp, true, false)?;
+                        let startcatch_index = ctx.prog.len();
+                        ctx.prog.push(Instruction::STARTCATCH(0));
+                        ctx.inc_catch_count();
+                        let body_res = ast_vec_bytecode(ctx, &args[1], ValStatus::Temp, true, false);
+                        ctx.dec_catch_count();
+                        body_res?;
+                        ctx.prog.push(Instruction::ENDCATCH);
+                        let current_len = ctx.prog.len();
+                        match &mut ctx.prog[startcatch_index] {
+                            Instruction::STARTCATCH(loc) => {
+                                *loc = current_len;
+                            }
+                            _ => unreachable!()
+                        }
+                        ctx.prog.push(Instruction::CATCHKIND);
+                        if direct_output {
+                            ctx.prog.push(Instruction::OUTPUTVAL);
+                        }
+                        Ok(true)
+                    },
+                    // everything the body outputs is changed on its way out, including
+                    // what functions called inside it output, not just its value
+                    "transform" => {
+                        if args.len() != 2 {
+                            return Err(InternalASTErrors::InvalidArgCount(var.line, String::from("transform"), args.len()));
+                        }
+                        let name = match &args[0][..] {
+                            [AST::String(name, _)] => name.to_string(),
+                            _ => String::new(),
+                        };
+                        let transform = match OutputTransform::from_name(&name) {
+                            Some(transform) => transform,
+                            None => {
+                                return Err(InternalASTErrors::UnknownTransform(var.line, name));
+                            }
+                        };
+                        // run like a catch block that doesn't catch, so the
+                        // output can be sent on once the body has finished
+                        ctx.prog.push(Instruction::STARTTRANSFORM(transform, direct_output));
+                        ctx.inc_catch_count();
+                        let body_res = if direct_output {
+                            // output straight from the body keeps its text apart
+                            // from its values, so escaping only happens to the values
+                            match ast_vec_bytecode(ctx, &args[1], ValStatus::Returned, false, true) {
+                                Ok(_) | Err(InternalASTErrors::LoopJumpCutoff) => {
+                                    ctx.prog.push(Instruction::PUSHNIL);
+                                    Ok(())
+                                },
+                                Err(v) => Err(v),
+                            }
+                        } else {
+                            ast_vec_bytecode(ctx, &args[1], ValStatus::Temp, true, false)
+                        };
+                        ctx.dec_catch_count();
+                        body_res?;
+                        ctx.prog.push(Instruction::ENDCATCH);
+                        Ok(!direct_output)
+                    },
+                    "with" => {
+                        if args.len() != 4 {
+                            return Err(InternalASTErrors::InvalidArgCount(var.line, String::from("with"), args.len()));
+                        }
+                        let ident = match &args[0][..] {
+                            [AST::String(ident, _)] => ident.clone(),
+                            _ => {
+                                return Err(InternalASTErrors::InvalidIdentifier(var.line, String::from("with")));
+                            }
+                        };
+                        ast_vec_bytecode(ctx, &args[1], ValStatus::Temp, true, false)?;
+                        ctx.prog.push(Instruction::SETVAR(ident.clone()));
+                        ctx.set_block_args(0);
+
+                        // the body is run like a catch block, so that the cleanup can
+                        // run before any thrown value carries on up the stack
+                        // breaks and continues in the body run the cleanup themselves
+                        let startcatch_index = ctx.prog.len();
+                        ctx.prog.push(Instruction::STARTCATCH(0));
+                        ctx.inc_catch_count();
+                        if let Some(cur_loop) = &mut ctx.current_loop {
+                            cur_loop.cleanups.push((ident.clone(), args[2].clone()));
+                        }
+                        let body_res = ast_vec_bytecode(ctx, &args[3], ValStatus::Temp, true, false);
+                        if let Some(cur_loop) = &mut ctx.current_loop {
+                            cur_loop.cleanups.pop();
+                        }
+                        ctx.dec_catch_count();
+                        body_res?;
+                        ctx.prog.push(Instruction::ENDCATCH);
+                        let current_len = ctx.prog.len();
+                        match &mut ctx.prog[startcatch_index] {
+                            Instruction::STARTCATCH(loc) => {
+                                *loc = current_len;
+                            }
+                            _ => unreachable!()
+                        }
+
+                        ast_with_cleanup(ctx, &ident, &args[2])?;
+                        ctx.set_block_args(1);
+                        ctx.prog.push(Instruction::UNWRAPCATCH);
+                        if direct_output {
+                            ctx.prog.push(Instruction::OUTPUTVAL);
+                        }
+                        Ok(true)
+                    },
                     "void" => {
                         if args.len() != 1 {
-                            return Err(InternalASTErrors::InvalidArgCount(String::from("void"), args.len()));
+                            return Err(InternalASTErrors::InvalidArgCount(var.line, String::from("void"), args.len()));
                         }
                         ast_vec_bytecode(ctx, &args[0], ValStatus::Temp, false, false)?;
                         ctx.prog.push(Instruction::DROP(1));
                         Ok(false)
                     },
-                    "while" => {
+                    // {let:a:1:b:2:body;} runs the body in a scope of its own, with a
+                    // and b set in it, so nothing set in the body is left behind after
+                    // the values are worked out first, in the scope outside of it
+                    "let" => {
+                        if args.len() % 2 == 0 {
+                            return Err(InternalASTErrors::InvalidArgCount(var.line, String::from("let"), args.len()));
+                        }
+                        let (body, pairs) = args.split_last().unwrap();
+                        let mut names = Vec::with_capacity(pairs.len() / 2);
+                        for pair in pairs.chunks(2) {
+                            match &pair[0][..] {
+                                [AST::String(name, _)] => {
+                                    names.push(name.to_owned());
+                                },
+                                _ => {
+                                    return Err(InternalASTErrors::InvalidIdentifier(var.line, String::from("let")));
+                                }
+                            }
+                            ast_vec_bytecode(ctx, &pair[1], ValStatus::Temp, true, false)?;
+                        }
+                        ctx.prog.push(Instruction::PUSHSCOPE(names));
+                        ctx.set_block_args(0);
+                        ctx.inc_scope_count();
+                        let body_res = ast_vec_bytecode(ctx, body, ValStatus::Returned, false, direct_output);
+                        ctx.dec_scope_count();
+                        match body_res {
+                            Ok(_) | Err(InternalASTErrors::LoopJumpCutoff) => {},
+                            Err(v) => return Err(v),
+                        }
+                        ctx.prog.push(Instruction::POPSCOPE(1));
+                        Ok(true)
+                    },
+                    // runs each arg in turn, and gives the value of the last one
+                    // rather than all of them joined together
+                    "do" => {
+                        let (last, rest) = match args.split_last() {
+                            Some(v) => v,
+                            None => {
+                                return Err(InternalASTErrors::InvalidArgCount(var.line, String::from("do"), 0));
+                            }
+                        };
+                        for arg in rest {
+                            ast_vec_bytecode(ctx, arg, ValStatus::Temp, false, false)?;
+                            ctx.prog.push(Instruction::DROP(1));
+                        }
+                        match ast_vec_bytecode(ctx, last, ValStatus::Returned, false, direct_output) {
+                            Ok(_) | Err(InternalASTErrors::LoopJumpCutoff) => {},
+                            Err(v) => return Err(v),
+                        }
+                        Ok(true)
+                    },
+                    // void throws away both its output and its value, where discard
+                    // only throws away the value, and always outputs what it runs,
+                    // even in the middle of something that isn't being output
+                    "discard" => {
+                        if args.len() != 1 {
+                            return Err(InternalASTErrors::InvalidArgCount(var.line, String::from("discard"), args.len()));
+                        }
+                        ast_vec_bytecode(ctx, &args[0], ValStatus::Temp, false, false)?;
+                        ctx.prog.push(Instruction::OUTPUTVAL);
+                        Ok(false)
+                    },
+                    // voidwhile is while run only for what it does, so each time round,
+                    // the body's value is dropped instead of being kept until the end
+                    "while" | "voidwhile" => {
                         if args.len() != 2 {
-                            return Err(InternalASTErrors::InvalidArgCount(String::from("while"), args.len()));
+                            return Err(InternalASTErrors::InvalidArgCount(var.line, s.to_owned(), args.len()));
                         }
+                        let keep_vals = &s[..] == "while";
+                        let body_output = direct_output && keep_vals;
+                        let iter_end = if keep_vals {Instruction::LOOPINCR} else {Instruction::DROP(1)};
                         ctx.prog.push(Instruction::WHILESTART);
                         let test_start = ctx.prog.len();
                         // this value is technically outside of the while body, so
@@ -360,21 +809,23 @@ fn ast_bytecode(ctx: &mut CompilerCtx, ast: &AST, direct_output: bool) -> Result
                                 breaks: Vec::new(),
                                 continues: Vec::new(),
                                 catch_count: 0,
+                                scope_count: 0,
+                                cleanups: Vec::new(),
                                 val_counts: Vec::new(),
                             })
                         );
 
                         let false_jump = ctx.prog.len();
                         ctx.prog.push(Instruction::IFFALSE(0));
-                        match ast_vec_bytecode(ctx, &args[1], ValStatus::Returned, false, direct_output) {
+                        match ast_vec_bytecode(ctx, &args[1], ValStatus::Returned, false, body_output) {
                             Ok(_) | Err(InternalASTErrors::LoopJumpCutoff) => {},
                             Err(v) => return Err(v),
                         }
                         let continue_jump = ctx.prog.len();
-                        ctx.prog.push(Instruction::LOOPINCR);
+                        ctx.prog.push(iter_end.clone());
                         ctx.prog.push(Instruction::GOTO(test_start));
                         let loop_end = ctx.prog.len();
-                        ctx.prog.push(Instruction::LOOPINCR);
+                        ctx.prog.push(iter_end);
                         match &mut ctx.prog[false_jump] {
                             Instruction::IFFALSE(ptr) => {
                                 *ptr = loop_end+1;
@@ -405,17 +856,22 @@ fn ast_bytecode(ctx: &mut CompilerCtx, ast: &AST, direct_output: bool) -> Result
                         } else {
                             panic!("INTERNAL ERROR: while loop data overwritten inside loop")
                         }
-                        ctx.prog.push(Instruction::LOOPEND(!direct_output));
-                        Ok(true)
+                        if keep_vals {
+                            ctx.prog.push(Instruction::LOOPEND(!direct_output));
+                            Ok(true)
+                        } else {
+                            ctx.prog.push(Instruction::LOOPEND(false));
+                            Ok(false)
+                        }
                     },
                     "for" => {
                         if args.len() < 3 || args.len() > 5 {
-                            return Err(InternalASTErrors::InvalidArgCount(String::from("for"), args.len()));
+                            return Err(InternalASTErrors::InvalidArgCount(var.line, String::from("for"), args.len()));
                         }
                         let ident = match &args[0][..] {
                             [AST::String(ident, _)] => ident.clone(),
                             _ => {
-                                return Err(InternalASTErrors::InvalidIdentifier(String::from("for")));
+                                return Err(InternalASTErrors::InvalidIdentifier(var.line, String::from("for")));
                             }
                         };
                         ast_vec_bytecode(ctx, &args[0], ValStatus::Temp, true, false)?;
@@ -448,6 +904,8 @@ fn ast_bytecode(ctx: &mut CompilerCtx, ast: &AST, direct_output: bool) -> Result
                                 breaks: Vec::new(),
                                 continues: Vec::new(),
                                 catch_count: 0,
+                                scope_count: 0,
+                                cleanups: Vec::new(),
                                 val_counts: Vec::new(),
                             })
                         );
@@ -496,12 +954,12 @@ fn ast_bytecode(ctx: &mut CompilerCtx, ast: &AST, direct_output: bool) -> Result
                     }
                     "foreach" => {
                         if args.len() != 3 {
-                            return Err(InternalASTErrors::InvalidArgCount(String::from("foreach"), args.len()));
+                            return Err(InternalASTErrors::InvalidArgCount(var.line, String::from("foreach"), args.len()));
                         }
                         let ident = match &args[0][..] {
                             [AST::String(ident, _)] => ident.clone(),
                             _ => {
-                                return Err(InternalASTErrors::InvalidIdentifier(String::from("foreach")));
+                                return Err(InternalASTErrors::InvalidIdentifier(var.line, String::from("foreach")));
                             }
                         };
                         ast_vec_bytecode(ctx, &args[1], ValStatus::Temp, true, false)?;
@@ -516,6 +974,8 @@ fn ast_bytecode(ctx: &mut CompilerCtx, ast: &AST, direct_output: bool) -> Result
                                 breaks: Vec::new(),
                                 continues: Vec::new(),
                                 catch_count: 0,
+                                scope_count: 0,
+                                cleanups: Vec::new(),
                                 val_counts: Vec::new(),
                             })
                         );
@@ -561,11 +1021,69 @@ fn ast_bytecode(ctx: &mut CompilerCtx, ast: &AST, direct_output: bool) -> Result
                         ctx.prog.push(Instruction::LOOPEND(!direct_output));
                         Ok(true)
                     }
+                    // for and foreach, except that the body gets a scope of its own each
+                    // time round, with the loop variable in it, so closures made in the
+                    // body each keep the value from their own iteration
+                    "letfor" | "letforeach" => {
+                        if args.len() < 2 {
+                            return Err(InternalASTErrors::InvalidArgCount(var.line, s.to_owned(), args.len()));
+                        }
+                        let ident = match &args[0][..] {
+                            [AST::String(ident, _)] => ident,
+                            _ => {
+                                return Err(InternalASTErrors::InvalidIdentifier(var.line, s.to_owned()));
+                            }
+                        };
+                        ast_bytecode(ctx, &scoped_loop(&s[3..], ident, args, var.line), direct_output)
+                    },
+                    "listcomp" => {
+                        if args.len() != 3 && args.len() != 4 {
+                            return Err(InternalASTErrors::InvalidArgCount(var.line, String::from("listcomp"), args.len()));
+                        }
+                        let ident = match &args[1][..] {
+                            [AST::String(ident, _)] => ident.clone(),
+                            _ => {
+                                return Err(InternalASTErrors::InvalidIdentifier(var.line, String::from("listcomp")));
+                            }
+                        };
+                        ast_vec_bytecode(ctx, &args[2], ValStatus::Temp, true, false)?;
+                        ctx.set_block_args(0);
+                        ctx.prog.push(Instruction::FOREACHSTART(ident));
+                        let test_start = ctx.prog.len();
+                        ctx.prog.push(Instruction::FOREACHITER(0));
+
+                        // break and continue can't be used inside a comprehension,
+                        // so it is compiled as if it weren't inside a loop at all
+                        let outer_loop = ctx.current_loop.take();
+                        let body_res = (|| {
+                            if let Some(cond) = args.get(3) {
+                                ast_vec_bytecode(ctx, cond, ValStatus::Temp, false, false)?;
+                                ctx.prog.push(Instruction::IFFALSE(test_start));
+                            }
+                            ast_vec_bytecode(ctx, &args[0], ValStatus::Temp, false, false)
+                        })();
+                        ctx.current_loop = outer_loop;
+                        body_res?;
+                        ctx.prog.push(Instruction::LOOPINCR);
+                        ctx.prog.push(Instruction::GOTO(test_start));
+                        let loop_end = ctx.prog.len();
+                        match &mut ctx.prog[test_start] {
+                            Instruction::FOREACHITER(ptr) => {
+                                *ptr = loop_end;
+                            },
+                            _ => unreachable!(),
+                        }
+                        ctx.prog.push(Instruction::LOOPENDLIST);
+                        if direct_output {
+                            ctx.prog.push(Instruction::OUTPUTVAL);
+                        }
+                        Ok(true)
+                    }
                     "continue" => {
                         if !args.is_empty() {
-                            return Err(InternalASTErrors::InvalidArgCount(String::from("continue"), args.len()));
+                            return Err(InternalASTErrors::InvalidArgCount(var.line, String::from("continue"), args.len()));
                         }
-                        if let Some(LoopJumps{continues, val_counts, catch_count, ..}) = &mut ctx.current_loop {
+                        let (cleanups, scope_count) = if let Some(LoopJumps{val_counts, catch_count, cleanups, scope_count, ..}) = &mut ctx.current_loop {
                             let (temp_vals, ret_vals) = count_stack_vals(val_counts);
                             if temp_vals > 0 {
                                 ctx.prog.push(Instruction::DROP(temp_vals));
@@ -584,18 +1102,26 @@ fn ast_bytecode(ctx: &mut CompilerCtx, ast: &AST, direct_output: bool) -> Result
                             if *catch_count > 0 {
                                 ctx.prog.push(Instruction::UNWINDCATCH(*catch_count));
                             }
-                            continues.push(ctx.prog.len());
-                            ctx.prog.push(Instruction::GOTO(0));
-                            Err(InternalASTErrors::LoopJumpCutoff)
+                            (cleanups.clone(), *scope_count)
                         } else {
-                            return Err(InternalASTErrors::ContinueOutsideOfLoop);
+                            return Err(InternalASTErrors::ContinueOutsideOfLoop(var.line));
+                        };
+                        ast_loop_cleanups(ctx, &cleanups)?;
+                        // the scopes go last, as the cleanups may use what was set in them
+                        if scope_count > 0 {
+                            ctx.prog.push(Instruction::POPSCOPE(scope_count));
                         }
+                        if let Some(LoopJumps{continues, ..}) = &mut ctx.current_loop {
+                            continues.push(ctx.prog.len());
+                        }
+                        ctx.prog.push(Instruction::GOTO(0));
+                        Err(InternalASTErrors::LoopJumpCutoff)
                     },
                     "break" => {
                         if !args.is_empty() {
-                            return Err(InternalASTErrors::InvalidArgCount(String::from("break"), args.len()));
+                            return Err(InternalASTErrors::InvalidArgCount(var.line, String::from("break"), args.len()));
                         }
-                        if let Some(LoopJumps{breaks, val_counts, catch_count, ..}) = &mut ctx.current_loop {
+                        let (cleanups, scope_count) = if let Some(LoopJumps{val_counts, catch_count, cleanups, scope_count, ..}) = &mut ctx.current_loop {
                             let (temp_vals, ret_vals) = count_stack_vals(val_counts);
                             if temp_vals > 0 {
                                 ctx.prog.push(Instruction::DROP(temp_vals));
@@ -614,12 +1140,20 @@ fn ast_bytecode(ctx: &mut CompilerCtx, ast: &AST, direct_output: bool) -> Result
                             if *catch_count > 0 {
                                 ctx.prog.push(Instruction::UNWINDCATCH(*catch_count));
                             }
-                            breaks.push(ctx.prog.len());
-                            ctx.prog.push(Instruction::GOTO(0));
-                            Err(InternalASTErrors::LoopJumpCutoff)
+                            (cleanups.clone(), *scope_count)
                         } else {
-                            return Err(InternalASTErrors::BreakOutsideOfLoop);
+                            return Err(InternalASTErrors::BreakOutsideOfLoop(var.line));
+                        };
+                        ast_loop_cleanups(ctx, &cleanups)?;
+                        // the scopes go last, as the cleanups may use what was set in them
+                        if scope_count > 0 {
+                            ctx.prog.push(Instruction::POPSCOPE(scope_count));
                         }
+                        if let Some(LoopJumps{breaks, ..}) = &mut ctx.current_loop {
+                            breaks.push(ctx.prog.len());
+                        }
+                        ctx.prog.push(Instruction::GOTO(0));
+                        Err(InternalASTErrors::LoopJumpCutoff)
                     },
                     _ => {
                         ast_var_access(ctx, var, direct_output)?;
@@ -635,7 +1169,16 @@ fn ast_bytecode(ctx: &mut CompilerCtx, ast: &AST, direct_output: bool) -> Result
         AST::SetVar(var, val) => {
             match (&var.value[..], &var.accessors[..]) {
                 ([AST::String(s, _)], []) => {
-                    ast_vec_bytecode(ctx, val, ValStatus::Temp, true, false)?;
+                    // functions set straight to a name, like with {func:...}, are named after it
+                    match lambda_args(val) {
+                        Some(args) if lambda_self_name(args).is_none() => {
+                            ast_compile_function(ctx, args, Some(s.to_owned()), var.line)?;
+                            ctx.set_block_args(1);
+                        },
+                        _ => {
+                            ast_vec_bytecode(ctx, val, ValStatus::Temp, true, false)?;
+                        },
+                    }
                     ctx.prog.push(Instruction::SETVAR(s.to_owned()));
                 },
                 ([AST::String(s, _)], _) => {
@@ -655,13 +1198,16 @@ fn ast_bytecode(ctx: &mut CompilerCtx, ast: &AST, direct_output: bool) -> Result
                             ast_vec_bytecode(ctx, val, ValStatus::Temp, true, false)?;
                             ctx.prog.push(Instruction::SETATTR);
                         },
-                        Accessor::Call(_) => {
-                            return Err(InternalASTErrors::CannotSetFunctionCall);
+                        Accessor::Slice(_) => {
+                            return Err(InternalASTErrors::CannotSetSlice(var.line));
+                        },
+                        Accessor::Call(_) | Accessor::SpreadCall(_) => {
+                            return Err(InternalASTErrors::CannotSetFunctionCall(var.line));
                         },
                     }
                 },
                 (_, []) => {
-                    return Err(InternalASTErrors::EmptySetCall);
+                    return Err(InternalASTErrors::EmptySetCall(var.line));
                 }
                 _ => {
                     ast_vec_bytecode(ctx, &var.value, ValStatus::Temp, true, false)?;
@@ -680,10 +1226,94 @@ fn ast_bytecode(ctx: &mut CompilerCtx, ast: &AST, direct_output: bool) -> Result
                             ast_vec_bytecode(ctx, val, ValStatus::Temp, true, false)?;
                             ctx.prog.push(Instruction::SETATTR);
                         },
-                        Accessor::Call(_) => {
-                            return Err(InternalASTErrors::CannotSetFunctionCall);
+                        Accessor::Slice(_) => {
+                            return Err(InternalASTErrors::CannotSetSlice(var.line));
+                        },
+                        Accessor::Call(_) | Accessor::SpreadCall(_) => {
+                            return Err(InternalASTErrors::CannotSetFunctionCall(var.line));
+                        },
+                    }
+                },
+            }
+            Ok(false)
+        },
+        AST::SetUnpack(names, val) => {
+            ast_vec_bytecode(ctx, val, ValStatus::Temp, true, false)?;
+            ctx.prog.push(Instruction::UNPACKLIST(names.len()));
+            // the items are pushed in order, so the last name is set first
+            for name in names.iter().rev() {
+                ctx.prog.push(Instruction::SETVAR(name.to_owned()));
+            }
+            Ok(false)
+        },
+        AST::SetUnpackMap(names, val) => {
+            ast_vec_bytecode(ctx, val, ValStatus::Temp, true, false)?;
+            ctx.prog.push(Instruction::UNPACKMAP(names.clone()));
+            for name in names.iter().rev() {
+                ctx.prog.push(Instruction::SETVAR(name.to_owned()));
+            }
+            Ok(false)
+        },
+        AST::SetOp(var, args) => {
+            let (op, operands) = match args.split_first() {
+                Some(v) => v,
+                None => {
+                    return Err(InternalASTErrors::InvalidArgCount(var.line, String::from("setop"), 0));
+                }
+            };
+            let argc = operands.len() + 1;
+            match (&var.value[..], &var.accessors[..]) {
+                ([AST::String(s, _)], []) => {
+                    ast_setop_func(ctx, op)?;
+                    ctx.prog.push(Instruction::GETVAR(s.to_owned()));
+                    ctx.set_block_args(2);
+                    for operand in operands {
+                        ast_vec_bytecode(ctx, operand, ValStatus::Temp, true, false)?;
+                    }
+                    ctx.prog.push(Instruction::CALLFUNC(argc, false));
+                    ctx.prog.push(Instruction::SETVAR(s.to_owned()));
+                },
+                (_, []) => {
+                    return Err(InternalASTErrors::EmptySetCall(var.line));
+                },
+                _ => {
+                    match &var.value[..] {
+                        [AST::String(s, _)] => {
+                            ctx.prog.push(Instruction::GETVAR(s.to_owned()));
+                            ctx.set_block_args(1);
+                        },
+                        _ => {
+                            ast_vec_bytecode(ctx, &var.value, ValStatus::Temp, true, false)?;
                         },
                     }
+                    for accessor in &var.accessors[..var.accessors.len()-1] {
+                        ast_accessor_bytecode(ctx, accessor)?;
+                    }
+                    let (arg, get, set) = match var.accessors.last().unwrap() {
+                        Accessor::Index(arg) => (arg, Instruction::GETINDEX, Instruction::SETINDEX),
+                        Accessor::Attr(arg) => (arg, Instruction::GETATTR, Instruction::SETATTR),
+                        Accessor::Slice(_) => {
+                            return Err(InternalASTErrors::CannotSetSlice(var.line));
+                        },
+                        Accessor::Call(_) | Accessor::SpreadCall(_) => {
+                            return Err(InternalASTErrors::CannotSetFunctionCall(var.line));
+                        },
+                    };
+                    // the container and index are kept underneath for the set,
+                    // and a copy of them is used to get the current value
+                    ast_vec_bytecode(ctx, arg, ValStatus::Temp, true, false)?;
+                    ctx.prog.push(Instruction::DUP(2));
+                    ctx.prog.push(get);
+                    ctx.set_block_args(3);
+                    // the op goes underneath the current value, as its first arg
+                    ast_setop_func(ctx, op)?;
+                    ctx.set_block_args(4);
+                    ctx.prog.push(Instruction::SWAP);
+                    for operand in operands {
+                        ast_vec_bytecode(ctx, operand, ValStatus::Temp, true, false)?;
+                    }
+                    ctx.prog.push(Instruction::CALLFUNC(argc, false));
+                    ctx.prog.push(set);
                 },
             }
             Ok(false)
@@ -708,13 +1338,16 @@ fn ast_bytecode(ctx: &mut CompilerCtx, ast: &AST, direct_output: bool) -> Result
                             ast_vec_bytecode(ctx, arg, ValStatus::Temp, true, false)?;
                             ctx.prog.push(Instruction::DELATTR);
                         },
-                        Accessor::Call(_) => {
-                            return Err(InternalASTErrors::CannotDelFunctionCall);
+                        Accessor::Slice(_) => {
+                            return Err(InternalASTErrors::CannotDelSlice(var.line));
+                        },
+                        Accessor::Call(_) | Accessor::SpreadCall(_) => {
+                            return Err(InternalASTErrors::CannotDelFunctionCall(var.line));
                         },
                     }
                 },
                 (_, []) => {
-                    return Err(InternalASTErrors::EmptyDelCall);
+                    return Err(InternalASTErrors::EmptyDelCall(var.line));
                 }
                 _ => {
                     ast_vec_bytecode(ctx, &var.value, ValStatus::Temp, true, false)?;
@@ -731,8 +1364,11 @@ fn ast_bytecode(ctx: &mut CompilerCtx, ast: &AST, direct_output: bool) -> Result
                             ast_vec_bytecode(ctx, arg, ValStatus::Temp, true, false)?;
                             ctx.prog.push(Instruction::DELATTR);
                         },
-                        Accessor::Call(_) => {
-                            return Err(InternalASTErrors::CannotDelFunctionCall);
+                        Accessor::Slice(_) => {
+                            return Err(InternalASTErrors::CannotDelSlice(var.line));
+                        },
+                        Accessor::Call(_) | Accessor::SpreadCall(_) => {
+                            return Err(InternalASTErrors::CannotDelFunctionCall(var.line));
                         },
                     }
                 },
@@ -742,7 +1378,86 @@ fn ast_bytecode(ctx: &mut CompilerCtx, ast: &AST, direct_output: bool) -> Result
     }
 }
 
-fn ast_compile_function(ctx: &mut CompilerCtx, args: &[Vec<AST>]) -> Result<(), InternalASTErrors> {
+// {lambda:@name:...;} names the function inside of its own body, so that
+// it can call itself without needing to be set to a variable first
+fn lambda_self_name(args: &[Vec<AST>]) -> Option<&str> {
+    match args {
+        [first, _, ..] => match &first[..] {
+            [AST::String(s, _)] if s.len() > 1 && s.starts_with('@') => Some(&s[1..]),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// the args of a value that is nothing but a non-empty {lambda:...;}
+fn lambda_args(val: &[AST]) -> Option<&[Vec<AST>]> {
+    match val {
+        [AST::Variable(VarAccess {value, accessors, ..})] => match (&value[..], &accessors[..]) {
+            ([AST::String(s, _)], [Accessor::Call(args)]) if s == "lambda" && !args.is_empty() => {
+                Some(args)
+            },
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn name_access(name: &str, line: usize) -> VarAccess {
+    VarAccess {
+        value: vec![AST::String(name.to_owned(), None)],
+        accessors: Vec::new(),
+        line,
+    }
+}
+
+fn lambda_ast(args: Vec<Vec<AST>>, line: usize) -> AST {
+    AST::Variable(VarAccess {
+        value: vec![AST::String(String::from("lambda"), None)],
+        accessors: vec![Accessor::Call(args)],
+        line,
+    })
+}
+
+// the same as {{lambda:{set:name:{lambda:...;};}{name};};}, so the name only
+// exists in the scope of a call that's done immediately, that the
+// function itself closes over
+fn self_named_lambda(name: &str, args: &[Vec<AST>], line: usize) -> AST {
+    let wrapper = lambda_ast(vec![vec![
+        AST::SetVar(name_access(name, line), vec![lambda_ast(args.to_vec(), line)]),
+        AST::Variable(name_access(name, line)),
+    ]], line);
+    AST::Variable(VarAccess {
+        value: vec![wrapper],
+        accessors: vec![Accessor::Call(Vec::new())],
+        line,
+    })
+}
+
+// the loop itself counts with a name that can't be written in the source,
+// so the only one the body can see is the one in its own scope
+fn scoped_loop(loop_name: &str, ident: &str, args: &[Vec<AST>], line: usize) -> AST {
+    let hidden = format!("{} {}", loop_name, ident);
+    let mut loop_args = args.to_vec();
+    loop_args[0] = vec![AST::String(hidden.clone(), None)];
+    let body = loop_args.pop().unwrap();
+    loop_args.push(vec![AST::Variable(VarAccess {
+        value: vec![AST::String(String::from("let"), None)],
+        accessors: vec![Accessor::Call(vec![
+            vec![AST::String(ident.to_owned(), None)],
+            vec![AST::Variable(name_access(&hidden, line))],
+            body,
+        ])],
+        line,
+    })]);
+    AST::Variable(VarAccess {
+        value: vec![AST::String(loop_name.to_owned(), None)],
+        accessors: vec![Accessor::Call(loop_args)],
+        line,
+    })
+}
+
+fn ast_compile_function(ctx: &mut CompilerCtx, args: &[Vec<AST>], name: Option<String>, line: usize) -> Result<(), InternalASTErrors> {
     let mut arg_names = Vec::with_capacity(args.len() - 1);
     for arg in &args[..args.len() - 1] {
         match &arg[..] {
@@ -750,7 +1465,7 @@ fn ast_compile_function(ctx: &mut CompilerCtx, args: &[Vec<AST>]) -> Result<(),
                 arg_names.push(s.to_owned());
             },
             _ => {
-                return Err(InternalASTErrors::InvalidIdentifier(String::from("lambda")));
+                return Err(InternalASTErrors::InvalidIdentifier(line, String::from("lambda")));
             }
         }
     }
@@ -760,6 +1475,7 @@ fn ast_compile_function(ctx: &mut CompilerCtx, args: &[Vec<AST>]) -> Result<(),
         funcs: Vec::new(),
         current_loop: None,
         in_function: true,
+        let_depth: 0,
     };
     match ast_vec_bytecode(&mut func_ctx, &args[args.len() - 1], ValStatus::Returned, true, true) {
         Err(InternalASTErrors::LoopJumpCutoff) => {
@@ -770,8 +1486,16 @@ fn ast_compile_function(ctx: &mut CompilerCtx, args: &[Vec<AST>]) -> Result<(),
     }
     func_ctx.prog.push(Instruction::END);
     ast_link_functions(&mut func_ctx);
+    // nested functions are included, as they can get to this args with nonlocal
+    let uses_args = func_ctx.prog.iter().any(|inst| match inst {
+        Instruction::GETVAR(name) |
+        Instruction::SETVAR(name) |
+        Instruction::DELVAR(name) |
+        Instruction::SETNONLOCAL(name) => name == "args",
+        _ => false,
+    });
     let current_len = ctx.prog.len();
-    ctx.prog.push(Instruction::CREATEFUNC(arg_names, 0, 0));
+    ctx.prog.push(Instruction::CREATEFUNC(name, arg_names, uses_args, 0, 0));
     ctx.funcs.push((current_len, func_ctx.prog));
     Ok(())
 }
@@ -781,7 +1505,7 @@ fn ast_link_functions(ctx: &mut CompilerCtx) {
     for (func_offset, inst) in funcs {
         let current_len = ctx.prog.len();
         match &mut ctx.prog[func_offset] {
-            Instruction::CREATEFUNC(_, offset, size) => {
+            Instruction::CREATEFUNC(_, _, _, offset, size) => {
                 *offset = current_len;
                 *size = inst.len();
             },
@@ -875,48 +1599,128 @@ fn ast_vec_bytecode(ctx: &mut CompilerCtx,
     Ok(())
 }
 
-pub fn generate_bytecode(ast: &[AST]) -> Result<Vec<Instruction>, ASTErrors> {
+// predefined holds any globals that are set before the program runs
+#[derive(Debug)]
+pub enum ProgramErrors {
+    JumpOutOfBounds(usize),
+    FunctionOutOfBounds(usize),
+    UnterminatedSegment(usize),
+}
+
+impl fmt::Display for ProgramErrors {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProgramErrors::JumpOutOfBounds(i) => {
+                write!(fmt, "jump out of bounds at instruction {}", i)
+            },
+            ProgramErrors::FunctionOutOfBounds(i) => {
+                write!(fmt, "function body out of bounds at instruction {}", i)
+            },
+            ProgramErrors::UnterminatedSegment(i) => {
+                write!(fmt, "code starting at instruction {} has no end", i)
+            },
+        }
+    }
+}
+
+impl std::error::Error for ProgramErrors {}
+
+// a segment is the top level code, or the body of a function, and runs with
+// its own instruction counter, so its jumps are relative to its start
+// its code runs up to its first END, and the bodies of the functions it
+// creates come after that, with their offsets also relative to the segment
+// offset is where the segment is in the whole program, for the error
+fn validate_segment(seg: &[Instruction], offset: usize) -> Result<(), ProgramErrors> {
+    let code_len = match seg.iter().position(|inst| matches!(inst, Instruction::END)) {
+        Some(i) => i + 1,
+        None => return Err(ProgramErrors::UnterminatedSegment(offset)),
+    };
+    for (i, inst) in seg[..code_len].iter().enumerate() {
+        match inst {
+            Instruction::IFFALSE(target) |
+            Instruction::IFNOTTYPE(_, target) |
+            Instruction::GOTO(target) |
+            Instruction::STARTCATCH(target) |
+            Instruction::FOREACHITER(target) |
+            Instruction::FORTEST(target) if *target >= code_len => {
+                return Err(ProgramErrors::JumpOutOfBounds(offset + i));
+            },
+            Instruction::CREATEFUNC(_, _, _, loc, size) => {
+                // a body has to come after the code creating it, which also
+                // stops a function from containing itself
+                let in_bounds = *loc >= code_len && matches!(loc.checked_add(*size), Some(end) if end <= seg.len());
+                if !in_bounds {
+                    return Err(ProgramErrors::FunctionOutOfBounds(offset + i));
+                }
+                validate_segment(&seg[*loc..*loc + *size], offset + *loc)?;
+            },
+            _ => {},
+        }
+    }
+    Ok(())
+}
+
+// checks that every jump and function body in a program stays within it,
+// so the interpreter can't be sent past the end by a bad program
+pub fn validate_program(prog: &[Instruction]) -> Result<(), ProgramErrors> {
+    validate_segment(prog, 0)
+}
+
+pub fn generate_bytecode(ast: &[AST], predefined: &[String]) -> Result<Vec<Instruction>, ASTErrors> {
     let mut ctx = CompilerCtx {
         prog: Vec::new(),
         funcs: Vec::new(),
         current_loop: None,
         in_function: false,
+        let_depth: 0,
     };
     match ast_vec_bytecode(&mut ctx, ast, ValStatus::Returned, true, true) {
         Ok(_) => {}
         Err(InternalASTErrors::LoopJumpCutoff) => {
             panic!("INTERNAL ERROR: loop jump cutoff leaked out of program");
         }
-        Err(InternalASTErrors::InvalidArgCount(n, c)) => {
-            return Err(ASTErrors::InvalidArgCount(n, c));
+        Err(InternalASTErrors::InvalidArgCount(line, n, c)) => {
+            return Err(ASTErrors::InvalidArgCount(line, n, c));
+        }
+        Err(InternalASTErrors::NonlocalInGlobalScope(line)) => {
+            return Err(ASTErrors::NonlocalInGlobalScope(line));
+        }
+        Err(InternalASTErrors::InvalidIdentifier(line, n)) => {
+            return Err(ASTErrors::InvalidIdentifier(line, n));
+        }
+        Err(InternalASTErrors::ContinueOutsideOfLoop(line)) => {
+            return Err(ASTErrors::ContinueOutsideOfLoop(line));
+        }
+        Err(InternalASTErrors::BreakOutsideOfLoop(line)) => {
+            return Err(ASTErrors::BreakOutsideOfLoop(line));
         }
-        Err(InternalASTErrors::NonlocalInGlobalScope) => {
-            return Err(ASTErrors::NonlocalInGlobalScope);
+        Err(InternalASTErrors::CannotSetFunctionCall(line)) => {
+            return Err(ASTErrors::CannotSetFunctionCall(line));
         }
-        Err(InternalASTErrors::InvalidIdentifier(n)) => {
-            return Err(ASTErrors::InvalidIdentifier(n));
+        Err(InternalASTErrors::EmptySetCall(line)) => {
+            return Err(ASTErrors::EmptySetCall(line));
         }
-        Err(InternalASTErrors::ContinueOutsideOfLoop) => {
-            return Err(ASTErrors::ContinueOutsideOfLoop);
+        Err(InternalASTErrors::CannotDelFunctionCall(line)) => {
+            return Err(ASTErrors::CannotDelFunctionCall(line));
         }
-        Err(InternalASTErrors::BreakOutsideOfLoop) => {
-            return Err(ASTErrors::BreakOutsideOfLoop);
+        Err(InternalASTErrors::EmptyDelCall(line)) => {
+            return Err(ASTErrors::EmptyDelCall(line));
         }
-        Err(InternalASTErrors::CannotSetFunctionCall) => {
-            return Err(ASTErrors::CannotSetFunctionCall);
+        Err(InternalASTErrors::CannotSetSlice(line)) => {
+            return Err(ASTErrors::CannotSetSlice(line));
         }
-        Err(InternalASTErrors::EmptySetCall) => {
-            return Err(ASTErrors::EmptySetCall);
+        Err(InternalASTErrors::CannotDelSlice(line)) => {
+            return Err(ASTErrors::CannotDelSlice(line));
         }
-        Err(InternalASTErrors::CannotDelFunctionCall) => {
-            return Err(ASTErrors::CannotDelFunctionCall);
+        Err(InternalASTErrors::UnknownTransform(line, name)) => {
+            return Err(ASTErrors::UnknownTransform(line, name));
         }
-        Err(InternalASTErrors::EmptyDelCall) => {
-            return Err(ASTErrors::EmptyDelCall);
+        Err(InternalASTErrors::UnknownType(line, name)) => {
+            return Err(ASTErrors::UnknownType(line, name));
         }
     }
     ctx.prog.push(Instruction::END);
     ast_link_functions(&mut ctx);
 
-    return Ok(ctx.prog);
+    return Ok(optimise::optimise(ctx.prog, predefined));
 }
\ No newline at end of file
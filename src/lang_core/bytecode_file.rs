@@ -0,0 +1,69 @@
+use crate::lang_core::bytecode::{Instruction, ProgramErrors, validate_program};
+use std::fs;
+use std::fmt;
+
+// bincode doesn't describe what it's encoding, so the header guards against
+// loading some other file, or one written by a build with different instructions
+const MAGIC: [u8; 4] = *b"IPBC";
+const FORMAT_VERSION: u32 = 8;
+
+#[derive(Debug)]
+pub enum BytecodeFileErrors {
+    CannotWrite,
+    CannotRead,
+    InvalidFormat,
+    WrongVersion(u32),
+    InvalidProgram(ProgramErrors),
+}
+
+impl fmt::Display for BytecodeFileErrors {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BytecodeFileErrors::CannotWrite => write!(fmt, "could not write file"),
+            BytecodeFileErrors::CannotRead => write!(fmt, "could not read file"),
+            BytecodeFileErrors::InvalidFormat => write!(fmt, "not a bytecode file"),
+            BytecodeFileErrors::WrongVersion(v) => {
+                write!(fmt, "file is format version {}, expected {}", v, FORMAT_VERSION)
+            },
+            BytecodeFileErrors::InvalidProgram(err) => write!(fmt, "invalid program: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for BytecodeFileErrors {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BytecodeFileErrors::InvalidProgram(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+pub fn save_program(prog: &[Instruction], filename: &str) -> Result<(), BytecodeFileErrors> {
+    let data = match bincode::serialize(&(MAGIC, FORMAT_VERSION, prog)) {
+        Ok(data) => data,
+        Err(_) => return Err(BytecodeFileErrors::InvalidFormat),
+    };
+    fs::write(filename, data).map_err(|_| BytecodeFileErrors::CannotWrite)
+}
+
+// anything loaded is validated, as it could have been changed since it was compiled
+pub fn load_program(filename: &str) -> Result<Vec<Instruction>, BytecodeFileErrors> {
+    let data = fs::read(filename).map_err(|_| BytecodeFileErrors::CannotRead)?;
+    let (magic, version): ([u8; 4], u32) = match bincode::deserialize(&data) {
+        Ok(header) => header,
+        Err(_) => return Err(BytecodeFileErrors::InvalidFormat),
+    };
+    if magic != MAGIC {
+        return Err(BytecodeFileErrors::InvalidFormat);
+    }
+    if version != FORMAT_VERSION {
+        return Err(BytecodeFileErrors::WrongVersion(version));
+    }
+    let (_, _, prog): ([u8; 4], u32, Vec<Instruction>) = match bincode::deserialize(&data) {
+        Ok(file) => file,
+        Err(_) => return Err(BytecodeFileErrors::InvalidFormat),
+    };
+    validate_program(&prog).map_err(BytecodeFileErrors::InvalidProgram)?;
+    Ok(prog)
+}
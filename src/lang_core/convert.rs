@@ -0,0 +1,196 @@
+use std::cell::Ref;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::rc::Rc;
+use crate::lang_core::interp::{VarValues, Gc, new_value, borrow_val, string_to_f64};
+
+// turns rust data into a value a program can use, for whatever is running
+// the interpreter to pass things in through Context::bind
+pub trait IntoVarValues {
+    fn into_varvalues(self) -> Gc<VarValues>;
+}
+
+pub fn to_varvalues<T: IntoVarValues>(val: T) -> Gc<VarValues> {
+    val.into_varvalues()
+}
+
+impl IntoVarValues for Gc<VarValues> {
+    fn into_varvalues(self) -> Gc<VarValues> {
+        self
+    }
+}
+
+impl IntoVarValues for String {
+    fn into_varvalues(self) -> Gc<VarValues> {
+        new_value(VarValues::Str(self))
+    }
+}
+
+impl IntoVarValues for &str {
+    fn into_varvalues(self) -> Gc<VarValues> {
+        new_value(VarValues::Str(self.to_owned()))
+    }
+}
+
+// numbers are all floats in the end, so big integers lose precision the
+// same way they would in a program
+macro_rules! num_into_varvalues {
+    ($($t:ty),+) => {
+        $(
+            impl IntoVarValues for $t {
+                fn into_varvalues(self) -> Gc<VarValues> {
+                    new_value(VarValues::Num(self as f64))
+                }
+            }
+        )+
+    };
+}
+
+num_into_varvalues!(f64, f32, i64, i32, u64, u32, usize);
+
+impl IntoVarValues for bool {
+    fn into_varvalues(self) -> Gc<VarValues> {
+        new_value(VarValues::Num(if self {1.0} else {0.0}))
+    }
+}
+
+impl<T: IntoVarValues> IntoVarValues for Option<T> {
+    fn into_varvalues(self) -> Gc<VarValues> {
+        match self {
+            Some(val) => val.into_varvalues(),
+            None => new_value(VarValues::Nil),
+        }
+    }
+}
+
+impl<T: IntoVarValues> IntoVarValues for Vec<T> {
+    fn into_varvalues(self) -> Gc<VarValues> {
+        let vals = self.into_iter().map(IntoVarValues::into_varvalues).collect();
+        new_value(VarValues::List(Rc::new(vals)))
+    }
+}
+
+impl<T: IntoVarValues> IntoVarValues for HashMap<String, T> {
+    fn into_varvalues(self) -> Gc<VarValues> {
+        let vals = self.into_iter().map(|(k, v)| (k, v.into_varvalues())).collect();
+        new_value(VarValues::Map(Rc::new(vals)))
+    }
+}
+
+impl<T: IntoVarValues> IntoVarValues for BTreeMap<String, T> {
+    fn into_varvalues(self) -> Gc<VarValues> {
+        let vals = self.into_iter().map(|(k, v)| (k, v.into_varvalues())).collect();
+        new_value(VarValues::Map(Rc::new(vals)))
+    }
+}
+
+#[derive(Debug)]
+pub enum ConvertErrors {
+    // what was wanted, and what the value was
+    WrongType(&'static str, &'static str),
+    CannotBorrow,
+}
+
+impl fmt::Display for ConvertErrors {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConvertErrors::WrongType(expected, found) => write!(fmt, "expected {}, got {}", expected, found),
+            ConvertErrors::CannotBorrow => write!(fmt, "value is in use"),
+        }
+    }
+}
+
+impl std::error::Error for ConvertErrors {}
+
+fn type_name(val: &VarValues) -> &'static str {
+    match val {
+        VarValues::Nil => "nil",
+        VarValues::Str(_) | VarValues::AstStr(..) | VarValues::Raw(_) => "string",
+        VarValues::Num(_) => "number",
+        VarValues::Func(..) | VarValues::RustFunc(_) | VarValues::RustClosure(_) => "function",
+        VarValues::CatchResult(..) => "catch result",
+        VarValues::List(_) => "list",
+        VarValues::Map(_) => "map",
+        VarValues::Set(_) => "set",
+        VarValues::Range(..) => "range",
+        VarValues::Iterator(_) => "iterator",
+    }
+}
+
+// the reverse of IntoVarValues, for getting rust data back out of what a
+// program gives, such as the values collected from its output
+pub trait FromVarValues: Sized {
+    fn from_varvalues(val: &Gc<VarValues>) -> Result<Self, ConvertErrors>;
+}
+
+pub fn from_varvalues<T: FromVarValues>(val: &Gc<VarValues>) -> Result<T, ConvertErrors> {
+    T::from_varvalues(val)
+}
+
+fn borrow(val: &Gc<VarValues>) -> Result<Ref<'_, VarValues>, ConvertErrors> {
+    borrow_val(val).map_err(|_| ConvertErrors::CannotBorrow)
+}
+
+impl FromVarValues for Gc<VarValues> {
+    fn from_varvalues(val: &Gc<VarValues>) -> Result<Self, ConvertErrors> {
+        Ok(Gc::clone(val))
+    }
+}
+
+// anything that prints as its own text, the same as a value being output
+impl FromVarValues for String {
+    fn from_varvalues(val: &Gc<VarValues>) -> Result<Self, ConvertErrors> {
+        match &*borrow(val)? {
+            v @ VarValues::Nil |
+            v @ VarValues::Str(_) |
+            v @ VarValues::AstStr(..) |
+            v @ VarValues::Raw(_) |
+            v @ VarValues::Num(_) => Ok(v.to_string()),
+            v => Err(ConvertErrors::WrongType("string", type_name(v))),
+        }
+    }
+}
+
+// strings are parsed, the same way the arithmetic builtins take them
+impl FromVarValues for f64 {
+    fn from_varvalues(val: &Gc<VarValues>) -> Result<Self, ConvertErrors> {
+        let v = borrow(val)?;
+        let n = match &*v {
+            VarValues::Num(n) |
+            VarValues::AstStr(_, Some(n)) => Some(*n),
+            VarValues::Str(s) |
+            VarValues::Raw(s) => string_to_f64(s),
+            _ => None,
+        };
+        n.ok_or_else(|| ConvertErrors::WrongType("number", type_name(&v)))
+    }
+}
+
+// every value is either truthy or not, so this never fails
+impl FromVarValues for bool {
+    fn from_varvalues(val: &Gc<VarValues>) -> Result<Self, ConvertErrors> {
+        Ok((&*borrow(val)?).into())
+    }
+}
+
+impl<T: FromVarValues> FromVarValues for Vec<T> {
+    fn from_varvalues(val: &Gc<VarValues>) -> Result<Self, ConvertErrors> {
+        match &*borrow(val)? {
+            VarValues::List(vals) => vals.iter().map(T::from_varvalues).collect(),
+            v => Err(ConvertErrors::WrongType("list", type_name(v))),
+        }
+    }
+}
+
+impl<T: FromVarValues> FromVarValues for HashMap<String, T> {
+    fn from_varvalues(val: &Gc<VarValues>) -> Result<Self, ConvertErrors> {
+        match &*borrow(val)? {
+            VarValues::Map(vals) => {
+                vals.iter()
+                    .map(|(k, v)| Ok((k.clone(), T::from_varvalues(v)?)))
+                    .collect()
+            },
+            v => Err(ConvertErrors::WrongType("map", type_name(v))),
+        }
+    }
+}
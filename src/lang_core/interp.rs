@@ -1,10 +1,17 @@
-use crate::bytecode::Instruction;
+use crate::bytecode::{Instruction, OutputTransform};
+use crate::lang_core::convert::IntoVarValues;
 use crate::builtins::register_builtins;
 use crate::builtins::math::val_to_f64;
 use crate::builtins::boolean::test_equality;
+use crate::builtins::string::html_escape;
 use std::cell::{RefCell, Ref, RefMut};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
 use std::fmt;
+use std::rc::Rc;
 use libgc::{Gc as Gc_};
 use std::ops::{Deref, DerefMut};
 
@@ -12,21 +19,80 @@ pub enum LangError {
     Throw(Gc<VarValues>),
     CatchUnwind(usize),
 }
-pub(crate) type LangResult<T> = Result<T, LangError>;
+pub type LangResult<T> = Result<T, LangError>;
+
+impl fmt::Debug for LangError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LangError::Throw(v) => {
+                fmt.debug_tuple("Throw")
+                    .field(v)
+                    .finish()
+            },
+            LangError::CatchUnwind(n) => {
+                fmt.debug_tuple("CatchUnwind")
+                    .field(n)
+                    .finish()
+            },
+        }
+    }
+}
+
+// the thrown value's string form, as it'd be shown if nothing caught it
+impl fmt::Display for LangError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LangError::Throw(v) => {
+                let msg = match v.try_borrow() {
+                    Ok(v) => v.to_string(),
+                    Err(_) => return write!(fmt, "<cannot borrow>"),
+                };
+                if msg.is_empty() {
+                    write!(fmt, "<empty value>")
+                } else {
+                    write!(fmt, "{}", msg)
+                }
+            },
+            LangError::CatchUnwind(_) => {
+                write!(fmt, "catchunwind escaped interpreter")
+            },
+        }
+    }
+}
+
+impl std::error::Error for LangError {}
 
 pub enum VarValues {
     Nil,
     Str(String),
     Num(f64),
-    AstStr(String, Option<f64>),
-    Func(Vec<String>, Vec<Instruction>, Gc<Namespace>),
-    RustFunc(fn(&mut Context, Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>>),
+    AstStr(Rc<str>, Option<f64>),
+    Func(Option<String>, Vec<String>, bool, Vec<Instruction>, Gc<Namespace>),
+    RustFunc(RustFn),
     RustClosure(Box<dyn Fn(&mut Context, Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>>>),
     CatchResult(bool, Gc<VarValues>),
-    List(Vec<Gc<VarValues>>),
-    Map(HashMap<String, Gc<VarValues>>),
+    // libgc's Gc keeps no count of who points at a value, so there's no way
+    // to tell whether a list or map is shared from the Gc alone. the storage goes
+    // behind an Rc instead, which copy shares rather than cloning, and any
+    // change made through Rc::make_mut only clones it if it's still shared
+    // only flat containers are shared like this: copy still looks at every
+    // item, and one holding any list, map or set is deep copied up front
+    List(Rc<Vec<Gc<VarValues>>>),
+    Map(Rc<HashMap<String, Gc<VarValues>>>),
+    Set(HashSet<String>),
+    Range(f64, f64, f64),
+    Iterator(IterFn),
+    // text that's already safe for the output, so an EscapingOutput passes
+    // it through as it is
+    Raw(String),
 }
 
+pub type RustFn = fn(&mut Context, Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>>;
+
+// a sequence that's worked out one item at a time as foreach asks for
+// it, giving None once it's run out
+pub type IterFn = Box<dyn FnMut(&mut Context) -> LangResult<Option<Gc<VarValues>>>>;
+
 // SAFETY: libgc needs these traits but the lib
 // only supports single-threaded applications,
 // so there won't be any concurrency issues
@@ -72,7 +138,37 @@ pub fn f64_to_string(n: f64) -> String {
     ret
 }
 
+pub fn range_len(start: f64, end: f64, step: f64) -> usize {
+    let len = ((end - start) / step).ceil();
+    if len > 0.0 {
+        len as usize
+    } else {
+        0
+    }
+}
+
+// underscores may group digits, as in 1_000_000 or 0xff_ff, but only
+// with a digit on both sides, so 1__0, _1 and 1_ aren't numbers
+fn strip_digit_separators(s: &str, hex: bool) -> Option<String> {
+    let is_digit = |c: Option<&u8>| match c {
+        Some(c) if hex => c.is_ascii_hexdigit(),
+        Some(c) => c.is_ascii_digit(),
+        None => false,
+    };
+    let bytes = s.as_bytes();
+    for (i, b) in bytes.iter().enumerate() {
+        if *b == b'_' && (i == 0 || !is_digit(bytes.get(i-1)) || !is_digit(bytes.get(i+1))) {
+            return None;
+        }
+    }
+    Some(s.replace('_', ""))
+}
+
 pub fn string_to_f64(s: &str) -> Option<f64> {
+    if s.contains('_') {
+        let hex = s.starts_with("0x");
+        return strip_digit_separators(s, hex).and_then(|s| string_to_f64(&s));
+    }
     if s.starts_with("0b") {
         u64::from_str_radix(&s[2..], 2).map(|v| v as f64).ok()
     } else if s.starts_with("0x") {
@@ -88,16 +184,20 @@ impl ToString for VarValues {
             VarValues::Nil => {
                 String::new()
             },
-            VarValues::Str(s) => {
+            VarValues::Str(s) |
+            VarValues::Raw(s) => {
                 s.clone()
             },
             VarValues::Num(v) => {
                 f64_to_string(*v)
             },
             VarValues::AstStr(s, _) => {
-                s.clone()
+                s.to_string()
             },
-            VarValues::Func(_, _, _) |
+            VarValues::Func(Some(name), _, _, _, _) => {
+                format!("<Function {}>", name)
+            },
+            VarValues::Func(None, _, _, _, _) |
             VarValues::RustFunc(_) |
             VarValues::RustClosure(_) => {
                 String::from("<Function>")
@@ -110,7 +210,16 @@ impl ToString for VarValues {
             },
             VarValues::Map(_) => {
                 String::from("<Map>")
-            }
+            },
+            VarValues::Set(_) => {
+                String::from("<Set>")
+            },
+            VarValues::Range(_, _, _) => {
+                String::from("<Range>")
+            },
+            VarValues::Iterator(_) => {
+                String::from("<Iterator>")
+            },
         }
     }
 }
@@ -122,14 +231,17 @@ impl From<&VarValues> for bool {
                 false
             },
             VarValues::Str(s) |
-            VarValues::AstStr(s, None) => {
+            VarValues::Raw(s) => {
                 !s.is_empty() && s != "0"
             },
+            VarValues::AstStr(s, None) => {
+                !s.is_empty() && &**s != "0"
+            },
             VarValues::Num(v) |
             VarValues::AstStr(_, Some(v)) => {
                 *v != 0.0
             },
-            VarValues::Func(_, _, _) |
+            VarValues::Func(_, _, _, _, _) |
             VarValues::RustFunc(_) |
             VarValues::RustClosure(_) => {
                 true
@@ -143,6 +255,16 @@ impl From<&VarValues> for bool {
             VarValues::Map(vs) => {
                 !vs.is_empty()
             },
+            VarValues::Set(vs) => {
+                !vs.is_empty()
+            },
+            VarValues::Range(start, end, step) => {
+                range_len(*start, *end, *step) > 0
+            },
+            // whether there's anything left can't be known without using it up
+            VarValues::Iterator(_) => {
+                true
+            },
         }
     }
 }
@@ -169,9 +291,11 @@ impl fmt::Debug for VarValues {
                     .field(v)
                     .finish()
             },
-            VarValues::Func(names, inst, _) => {
+            VarValues::Func(name, names, uses_args, inst, _) => {
                 fmt.debug_tuple("Func")
+                    .field(name)
                     .field(names)
+                    .field(uses_args)
                     .field(inst)
                     .field(&format_args!("_"))
                     .finish()
@@ -202,6 +326,28 @@ impl fmt::Debug for VarValues {
                     .field(vs)
                     .finish()
             },
+            VarValues::Set(vs) => {
+                fmt.debug_tuple("Set")
+                    .field(vs)
+                    .finish()
+            },
+            VarValues::Range(start, end, step) => {
+                fmt.debug_tuple("Range")
+                    .field(start)
+                    .field(end)
+                    .field(step)
+                    .finish()
+            },
+            VarValues::Iterator(_) => {
+                fmt.debug_tuple("Iterator")
+                    .field(&format_args!("_"))
+                    .finish()
+            },
+            VarValues::Raw(s) => {
+                fmt.debug_tuple("Raw")
+                    .field(s)
+                    .finish()
+            },
         }
     }
 }
@@ -235,19 +381,45 @@ pub fn borrow_val_mut<'a, T>(val: &'a Gc<T>) -> LangResult<RefMut<'a, T>> {
     }
 }
 
-fn validate_list_index(mut v: f64, max: usize) -> LangResult<usize> {
+// negative indexes count back from the end, so -1 is the last item
+pub(crate) fn wrap_index(v: f64, max: usize) -> f64 {
+    if v < 0.0 {
+        v + max as f64
+    } else {
+        v
+    }
+}
+
+fn validate_list_index(v: f64, max: usize) -> LangResult<usize> {
     if v.fract() != 0.0 {
         return throw_string!("invalid index");
     }
-    if v < 0.0 {
-        v += max as f64;
-    }
+    let v = wrap_index(v, max);
     if v < 0.0 || v as usize >= max {
         return throw_string!("index out of range");
     }
     Ok(v as usize)
 }
 
+// what sort of error a thrown value is, which is the kind key of a map, or
+// the name at the start of a builtin's <name:message> error
+pub(crate) fn error_kind(val: &VarValues) -> Option<String> {
+    match val {
+        VarValues::Map(vals) => match vals.get("kind") {
+            Some(kind) => Some(borrow_val(kind).ok()?.to_string()),
+            None => None,
+        },
+        v => {
+            let s = v.to_string();
+            let inner = s.strip_prefix('<')?.strip_suffix('>')?;
+            let end = inner.find(':')?;
+            Some(inner[..end].to_owned())
+        },
+    }
+}
+
+// strings are indexed by char rather than by byte, so a multi-byte char is
+// never split; {bytes:s;} gives the bytes themselves
 fn index_val_str(s: &str, index: f64) -> LangResult<Gc<VarValues>> {
     if index.fract() != 0.0 {
         return throw_string!("invalid index");
@@ -268,23 +440,108 @@ fn index_val_str(s: &str, index: f64) -> LangResult<Gc<VarValues>> {
     }
 }
 
+// an empty slice part (nil or "") falls back to its default, as in a[:3]
+fn slice_bound(val: &Gc<VarValues>) -> LangResult<Option<f64>> {
+    let v = match &*borrow_val(val)? {
+        VarValues::Nil => return Ok(None),
+        VarValues::Str(s) if s.is_empty() => return Ok(None),
+        VarValues::AstStr(s, None) if s.is_empty() => return Ok(None),
+        VarValues::Str(s) => {
+            match string_to_f64(s) {
+                Some(v) => v,
+                None => {
+                    return throw_string!("invalid index");
+                }
+            }
+        },
+        VarValues::Num(n) |
+        VarValues::AstStr(_, Some(n)) => {
+            *n
+        },
+        _ => {
+            return throw_string!("invalid index");
+        },
+    };
+    if v.fract() != 0.0 {
+        return throw_string!("invalid index");
+    }
+    Ok(Some(v))
+}
+
+// works out which items a slice covers, following python's rules: negative
+// bounds count from the end, and out of range bounds are clamped rather
+// than being an error
+fn slice_indices(len: usize, start: Option<f64>, end: Option<f64>, step: Option<f64>) -> LangResult<Vec<usize>> {
+    let step = step.unwrap_or(1.0);
+    if step == 0.0 {
+        return throw_string!("<slice:zero-size step>");
+    }
+    let len = len as f64;
+    let clamp = |v: f64, lower: f64, upper: f64| {
+        let v = if v < 0.0 { v + len } else { v };
+        v.max(lower).min(upper)
+    };
+    let mut ret = Vec::new();
+    if step > 0.0 {
+        let mut i = start.map_or(0.0, |v| clamp(v, 0.0, len));
+        let end = end.map_or(len, |v| clamp(v, 0.0, len));
+        while i < end {
+            ret.push(i as usize);
+            i += step;
+        }
+    } else {
+        let mut i = start.map_or(len - 1.0, |v| clamp(v, -1.0, len - 1.0));
+        let end = end.map_or(-1.0, |v| clamp(v, -1.0, len - 1.0));
+        while i > end {
+            ret.push(i as usize);
+            i += step;
+        }
+    }
+    Ok(ret)
+}
+
+fn args_to_string(args: &[Gc<VarValues>]) -> LangResult<String> {
+    let mut strings = Vec::with_capacity(args.len());
+    for arg in args {
+        strings.push(borrow_val(arg)?.to_string());
+    }
+    Ok(strings.join(", "))
+}
+
 impl VarValues {
     fn call(&self, ctx: &mut Context, args: Vec<Gc<VarValues>>, outputter: &mut dyn Outputter) -> LangResult<()> {
         match self {
-            VarValues::Func(names, inst, outer_scope) => {
-                let mut vars = HashMap::with_capacity(args.len());
-                if names.len() > args.len() {
-                    return throw_string!("expected {} args, got {}", names.len(), args.len());
+            VarValues::Func(name, names, uses_args, inst, outer_scope) => {
+                let mut vars = HashMap::with_capacity(names.len().max(args.len()));
+                let func_name = name.as_deref().unwrap_or("lambda");
+                // a function that reads args can be given fewer than it names,
+                // and the named params that weren't given are left as nil
+                if !*uses_args && names.len() > args.len() {
+                    return throw_string!("<{}:expected {} args, got {}>", func_name, names.len(), args.len());
                 }
-                for i in 0..names.len() {
-                    vars.insert(names[i].clone(), VarRefType::Value(Gc::clone(&args[i])));
+                // a function that uses args is taking any number on purpose
+                if ctx.strict_args && !*uses_args && names.len() < args.len() {
+                    return throw_string!("<{}:expected {} args, got {}>", func_name, names.len(), args.len());
+                }
+                // the args have to be turned into text before the list takes them
+                let trace_args = match ctx.trace_calls {
+                    true => Some(args_to_string(&args)?),
+                    false => None,
+                };
+                for (i, name) in names.iter().enumerate() {
+                    let val = match args.get(i) {
+                        Some(arg) => Gc::clone(arg),
+                        None => ctx.new_nil(),
+                    };
+                    vars.insert(name.clone(), VarRefType::Value(val));
                 }
-                if names.iter().all(|v| v != "args") {
+                // the list is only built for functions that mention args somewhere
+                if *uses_args && names.iter().all(|v| v != "args") {
                     vars.insert(
                         String::from("args"),
                         VarRefType::Value(
                             new_value(
-                                VarValues::List(args)
+                                VarValues::List(Rc::new(args))
                             )
                         )
                     );
@@ -295,9 +552,16 @@ impl VarValues {
                     outer_scope: Some(Gc::clone(&outer_scope)),
                 });
                 ctx.cur_scope = new_ns;
-                ctx.interpret(inst, outputter)?;
+                // the caller's scope has to come back even if the body throws,
+                // as the throw may be caught further up
+                ctx.call_depth += 1;
+                let ret = match trace_args {
+                    Some(text) => ctx.interpret_traced(inst, func_name, text, outputter),
+                    None => ctx.interpret(inst, outputter),
+                };
+                ctx.call_depth -= 1;
                 ctx.cur_scope = old_scope;
-                Ok(())
+                ret
             },
             VarValues::RustFunc(f) => {
                 let ret_val = f(ctx, args)?;
@@ -315,17 +579,68 @@ impl VarValues {
         }
     }
 
-    fn get_attr(&self, obj: Gc<VarValues>, index: Gc<VarValues>) -> LangResult<Gc<VarValues>> {
+    // what the type builtin and matchtype call each kind of value
+    // a string written as a number in the source counts as a number
+    pub(crate) fn type_name(&self) -> &'static str {
+        match self {
+            VarValues::Nil => "nil",
+            VarValues::Num(_) |
+            VarValues::AstStr(_, Some(_)) => "num",
+            VarValues::Str(_) |
+            VarValues::AstStr(_, None) |
+            VarValues::Raw(_) => "str",
+            VarValues::Func(..) |
+            VarValues::RustFunc(_) |
+            VarValues::RustClosure(_) => "func",
+            VarValues::CatchResult(..) => "catch",
+            VarValues::List(_) => "list",
+            VarValues::Map(_) => "map",
+            VarValues::Set(_) => "set",
+            VarValues::Range(..) => "range",
+            VarValues::Iterator(_) => "iterator",
+        }
+    }
+
+    // the text of either kind of string value
+    pub(crate) fn as_str(&self) -> Option<&str> {
+        match self {
+            VarValues::Str(s) => Some(s),
+            VarValues::AstStr(s, _) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn get_attr(&self, ctx: &Context, obj: Gc<VarValues>, index: Gc<VarValues>) -> LangResult<Gc<VarValues>> {
         match self {
             VarValues::List(vs) => {
                 let name = borrow_val(&index)?.to_string();
                 match &name[..] {
                     "push" => {
-                        let method = move |_ctx: &mut Context, args: Vec<Gc<VarValues>>| {
+                        let method = move |ctx: &mut Context, args: Vec<Gc<VarValues>>| {
+                            match &mut *borrow_val_mut(&obj)? {
+                                VarValues::List(vals) => {
+                                    Rc::make_mut(vals).extend(args);
+                                    Ok(ctx.new_nil())
+                                }
+                                _ => unreachable!()
+                            }
+                        };
+                        Ok(
+                            new_value(
+                                VarValues::RustClosure(Box::new(method))
+                            )
+                        )
+                    },
+                    "reverse" => {
+                        // reverses the list itself, rather than making a new one
+                        let method = move |ctx: &mut Context, args: Vec<Gc<VarValues>>| {
+                            if !args.is_empty() {
+                                return throw_string!("<list.reverse:expected 0 args, got {}>", args.len());
+                            }
                             match &mut *borrow_val_mut(&obj)? {
                                 VarValues::List(vals) => {
-                                    vals.extend(args);
-                                    Ok(new_value(VarValues::Nil))
+                                    Rc::make_mut(vals).reverse();
+                                    Ok(ctx.new_nil())
                                 }
                                 _ => unreachable!()
                             }
@@ -336,20 +651,32 @@ impl VarValues {
                             )
                         )
                     },
-                    "index" => {
+                    // index gives -1 when the item isn't there, which is truthy and
+                    // also a valid index, so find wraps the answer in a catch result:
+                    // it's only truthy when found, with the index as its value
+                    "index" | "find" => {
+                        let find = name == "find";
                         let method = move |_ctx: &mut Context, args: Vec<Gc<VarValues>>| {
+                            let name = if find {"find"} else {"index"};
                             if args.len() != 1 {
-                                return throw_string!("<list.index:expected 1 arg, got {}", args.len());
+                                return throw_string!("<list.{}:expected 1 arg, got {}>", name, args.len());
                             }
                             let arg = &args[0];
-                            match &mut *borrow_val_mut(&obj)? {
+                            match &*borrow_val(&obj)? {
                                 VarValues::List(vals) => {
+                                    let mut found = None;
                                     for i in 0..vals.len() {
                                         if test_equality(&vals[i], arg)? {
-                                            return Ok(new_value(VarValues::Num(i as f64)));
+                                            found = Some(i);
+                                            break;
                                         }
                                     }
-                                    Ok(new_value(VarValues::Num(-1.0)))
+                                    Ok(new_value(match (found, find) {
+                                        (Some(i), false) => VarValues::Num(i as f64),
+                                        (None, false) => VarValues::Num(-1.0),
+                                        (Some(i), true) => VarValues::CatchResult(true, new_value(VarValues::Num(i as f64))),
+                                        (None, true) => VarValues::CatchResult(false, new_value(VarValues::Nil)),
+                                    }))
                                 }
                                 _ => unreachable!()
                             }
@@ -363,6 +690,13 @@ impl VarValues {
                     "length" => {
                         Ok(new_value(VarValues::Num(vs.len() as f64)))
                     },
+                    "sum" => {
+                        let mut total = 0.0;
+                        for v in vs.iter() {
+                            total += val_to_f64(v, "sum")?;
+                        }
+                        Ok(new_value(VarValues::Num(total)))
+                    },
                     _ => {
                         throw_string!("invalid attr")
                     }
@@ -376,20 +710,38 @@ impl VarValues {
                     },
                     "keys" => {
                         Ok(new_value(
-                            VarValues::List(
-                                vals.keys()
+                            VarValues::List(Rc::new(
+                                ctx.map_keys(vals)
+                                .into_iter()
                                 .map(|v| {
                                     new_value(VarValues::Str(v.to_owned()))
                                 })
                                 .collect()
-                            )
+                            ))
+                        ))
+                    },
+                    // always in order, even when map_keys isn't
+                    "sortedkeys" => {
+                        let mut keys: Vec<&String> = vals.keys().collect();
+                        keys.sort();
+                        Ok(new_value(
+                            VarValues::List(Rc::new(
+                                keys.into_iter()
+                                .map(|v| {
+                                    new_value(VarValues::Str(v.to_owned()))
+                                })
+                                .collect()
+                            ))
                         ))
                     },
                     "values" => {
                         Ok(new_value(
-                            VarValues::List(
-                                vals.values().map(|v| *v).collect()
-                            )
+                            VarValues::List(Rc::new(
+                                ctx.map_keys(vals)
+                                .into_iter()
+                                .map(|k| vals[k])
+                                .collect()
+                            ))
                         ))
                     }
                     "contains" => {
@@ -399,7 +751,7 @@ impl VarValues {
                             }
                             let arg = &args[0];
                             let arg_str = borrow_val(arg)?.to_string();
-                            match &mut *borrow_val_mut(&obj)? {
+                            match &*borrow_val(&obj)? {
                                 VarValues::Map(vals) => {
                                     let ret = vals.contains_key(&arg_str);
                                     Ok(new_value(VarValues::Num(if ret {1.0} else {0.0})))
@@ -418,8 +770,102 @@ impl VarValues {
                     }
                 }
             },
-            VarValues::Str(s) |
-            VarValues::AstStr(s, _) => {
+            VarValues::Set(vals) => {
+                let name = borrow_val(&index)?.to_string();
+                match &name[..] {
+                    "length" => {
+                        Ok(new_value(VarValues::Num(vals.len() as f64)))
+                    },
+                    "items" => {
+                        // sorted, since the set itself has no order
+                        let mut items: Vec<&String> = vals.iter().collect();
+                        items.sort();
+                        Ok(new_value(
+                            VarValues::List(Rc::new(
+                                items.into_iter()
+                                .map(|v| {
+                                    new_value(VarValues::Str(v.to_owned()))
+                                })
+                                .collect()
+                            ))
+                        ))
+                    },
+                    "add" | "remove" => {
+                        let method = move |ctx: &mut Context, args: Vec<Gc<VarValues>>| {
+                            let mut items = Vec::with_capacity(args.len());
+                            for arg in &args {
+                                items.push(borrow_val(arg)?.to_string());
+                            }
+                            match &mut *borrow_val_mut(&obj)? {
+                                VarValues::Set(vals) => {
+                                    if name == "add" {
+                                        vals.extend(items);
+                                    } else {
+                                        for item in &items {
+                                            vals.remove(item);
+                                        }
+                                    }
+                                    Ok(ctx.new_nil())
+                                }
+                                _ => unreachable!()
+                            }
+                        };
+                        Ok(
+                            new_value(
+                                VarValues::RustClosure(Box::new(method))
+                            )
+                        )
+                    },
+                    "has" => {
+                        let method = move |_ctx: &mut Context, args: Vec<Gc<VarValues>>| {
+                            if args.len() != 1 {
+                                return throw_string!("<set.has:expected 1 arg, got {}>", args.len());
+                            }
+                            let arg_str = borrow_val(&args[0])?.to_string();
+                            match &*borrow_val(&obj)? {
+                                VarValues::Set(vals) => {
+                                    let ret = vals.contains(&arg_str);
+                                    Ok(new_value(VarValues::Num(if ret {1.0} else {0.0})))
+                                }
+                                _ => unreachable!()
+                            }
+                        };
+                        Ok(
+                            new_value(
+                                VarValues::RustClosure(Box::new(method))
+                            )
+                        )
+                    },
+                    "union" | "intersection" | "difference" => {
+                        let method = move |_ctx: &mut Context, args: Vec<Gc<VarValues>>| {
+                            if args.len() != 1 {
+                                return throw_string!("<set.{}:expected 1 arg, got {}>", name, args.len());
+                            }
+                            let (vals, other) = match (&*borrow_val(&obj)?, &*borrow_val(&args[0])?) {
+                                (VarValues::Set(vals), VarValues::Set(other)) => (vals.clone(), other.clone()),
+                                _ => return throw_string!("<set.{}:expected a set>", name),
+                            };
+                            let ret = match &name[..] {
+                                "union" => vals.union(&other).cloned().collect(),
+                                "intersection" => vals.intersection(&other).cloned().collect(),
+                                _ => vals.difference(&other).cloned().collect(),
+                            };
+                            Ok(new_value(VarValues::Set(ret)))
+                        };
+                        Ok(
+                            new_value(
+                                VarValues::RustClosure(Box::new(method))
+                            )
+                        )
+                    },
+                    _ => {
+                        throw_string!("invalid attr")
+                    }
+                }
+            },
+            VarValues::Str(_) |
+            VarValues::AstStr(_, _) => {
+                let s = self.as_str().unwrap();
                 let name = borrow_val(&index)?.to_string();
                 match &name[..] {
                     "length" => {
@@ -452,6 +898,17 @@ impl VarValues {
                     }
                 }
             },
+            VarValues::Range(start, end, step) => {
+                let name = borrow_val(&index)?.to_string();
+                match &name[..] {
+                    "length" => {
+                        Ok(new_value(VarValues::Num(range_len(*start, *end, *step) as f64)))
+                    },
+                    _ => {
+                        throw_string!("invalid attr")
+                    }
+                }
+            },
             VarValues::CatchResult(is_success, v) => {
                 let name = borrow_val(&index)?.to_string();
                 match &name[..] {
@@ -465,6 +922,29 @@ impl VarValues {
                     "value" => {
                         Ok(Gc::clone(v))
                     },
+                    "kind" => {
+                        match error_kind(&*borrow_val(v)?) {
+                            Some(kind) if !*is_success => Ok(new_value(VarValues::Str(kind))),
+                            _ => Ok(new_value(VarValues::Nil)),
+                        }
+                    },
+                    _ => {
+                        throw_string!("invalid attr")
+                    }
+                }
+            },
+            VarValues::Func(func_name, names, _, _, _) => {
+                let name = borrow_val(&index)?.to_string();
+                match &name[..] {
+                    "name" => {
+                        match func_name {
+                            Some(func_name) => Ok(new_value(VarValues::Str(func_name.clone()))),
+                            None => Ok(new_value(VarValues::Nil)),
+                        }
+                    },
+                    "arity" => {
+                        Ok(new_value(VarValues::Num(names.len() as f64)))
+                    },
                     _ => {
                         throw_string!("invalid attr")
                     }
@@ -505,8 +985,9 @@ impl VarValues {
                     None => return throw_string!("<map:{}:unknown key>", index),
                 }
             },
-            VarValues::Str(s) |
-            VarValues::AstStr(s, _) => {
+            VarValues::Str(_) |
+            VarValues::AstStr(_, _) => {
+                let s = self.as_str().unwrap();
                 let v = match &*borrow_val(&index)? {
                     VarValues::Str(s) => {
                         match string_to_f64(s) {
@@ -552,6 +1033,30 @@ impl VarValues {
         }
     }
 
+    fn get_slice(&self, start: Gc<VarValues>, end: Gc<VarValues>, step: Gc<VarValues>) -> LangResult<Gc<VarValues>> {
+        let (start, end, step) = (slice_bound(&start)?, slice_bound(&end)?, slice_bound(&step)?);
+        match self {
+            VarValues::List(vs) => {
+                let indices = slice_indices(vs.len(), start, end, step)?;
+                Ok(new_value(VarValues::List(Rc::new(
+                    indices.into_iter().map(|i| Gc::clone(&vs[i])).collect()
+                ))))
+            },
+            VarValues::Str(_) |
+            VarValues::AstStr(_, _) |
+            VarValues::Num(_) => {
+                let chars: Vec<char> = self.to_string().chars().collect();
+                let indices = slice_indices(chars.len(), start, end, step)?;
+                Ok(new_value(VarValues::Str(
+                    indices.into_iter().map(|i| chars[i]).collect()
+                )))
+            },
+            _ => {
+                throw_string!("cannot slice")
+            },
+        }
+    }
+
     fn set_index(&mut self, _obj: Gc<VarValues>, index: Gc<VarValues>, val: Gc<VarValues>) -> LangResult<()> {
         match self {
             VarValues::List(vs) => {
@@ -572,12 +1077,12 @@ impl VarValues {
                         return throw_string!("invalid index");
                     },
                 };
-                vs[v] = val;
+                Rc::make_mut(vs)[v] = val;
                 Ok(())
             },
             VarValues::Map(vals) => {
                 let index = borrow_val(&index)?.to_string();
-                vals.insert(index, val);
+                Rc::make_mut(vals).insert(index, val);
                 Ok(())
             },
             _ => {
@@ -610,12 +1115,12 @@ impl VarValues {
                         return throw_string!("invalid index");
                     },
                 };
-                vs.remove(v);
+                Rc::make_mut(vs).remove(v);
                 Ok(())
             }
             VarValues::Map(vals) => {
                 let index = borrow_val(&index)?.to_string();
-                vals.remove(&index);
+                Rc::make_mut(vals).remove(&index);
                 Ok(())
             },
             _ => {
@@ -655,6 +1160,13 @@ impl fmt::Debug for Namespace {
     }
 }
 
+// what the interpreter loop should do after an instruction has run
+enum InstFlow {
+    Next,
+    End,
+    EndCatch,
+}
+
 struct LoopFrame {
     stack_vals: usize,
     loop_data: LoopType,
@@ -673,7 +1185,18 @@ enum LoopType {
         list_index: usize,
         list_ref: Ref<'static, VarValues>,
         _list_gc_ref: Gc<VarValues>,
-    }
+    },
+    ForEachRange {
+        ident: String,
+        start: f64,
+        step: f64,
+        index: usize,
+        len: usize,
+    },
+    ForEachIter {
+        ident: String,
+        iter: Gc<VarValues>,
+    },
 }
 
 pub trait Outputter {
@@ -694,13 +1217,165 @@ impl Outputter for StdOutOutput {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EscapePolicy {
+    Html,
+    // each value becomes a single quoted word
+    Shell,
+    // for values written out between the quotes of a json string
+    Json,
+}
+
+impl EscapePolicy {
+    pub fn from_name(name: &str) -> Option<EscapePolicy> {
+        match name {
+            "html" => Some(EscapePolicy::Html),
+            "shell" => Some(EscapePolicy::Shell),
+            "json" => Some(EscapePolicy::Json),
+            _ => None,
+        }
+    }
+
+    fn escape(self, s: &str) -> String {
+        match self {
+            EscapePolicy::Html => html_escape(s),
+            EscapePolicy::Shell => format!("'{}'", s.replace('\'', "'\\''")),
+            EscapePolicy::Json => {
+                let mut out = String::with_capacity(s.len());
+                for c in s.chars() {
+                    match c {
+                        '"' => out.push_str("\\\""),
+                        '\\' => out.push_str("\\\\"),
+                        '\n' => out.push_str("\\n"),
+                        '\r' => out.push_str("\\r"),
+                        '\t' => out.push_str("\\t"),
+                        c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+                        c => out.push(c),
+                    }
+                }
+                out
+            },
+        }
+    }
+}
+
+// escapes every value the program outputs before passing it on, while the
+// program's own text, and values made with raw, go through untouched
+pub struct EscapingOutput<'a> {
+    pub inner: &'a mut dyn Outputter,
+    pub policy: EscapePolicy,
+}
+
+impl Outputter for EscapingOutput<'_> {
+    fn output_string(&mut self, s: &str, v: Option<f64>) {
+        self.inner.output_string(s, v);
+    }
+
+    fn output_value(&mut self, v: Gc<VarValues>) -> LangResult<()> {
+        let escaped = match &*borrow_val(&v)? {
+            VarValues::Raw(_) | VarValues::Nil => None,
+            val => Some(self.policy.escape(&val.to_string())),
+        };
+        match escaped {
+            Some(s) => self.inner.output_value(new_value(VarValues::Str(s))),
+            None => self.inner.output_value(v),
+        }
+    }
+}
+
+fn apply_transform(transform: OutputTransform, s: &str) -> String {
+    match transform {
+        OutputTransform::Upper => s.to_uppercase(),
+        OutputTransform::Lower => s.to_lowercase(),
+        OutputTransform::Trim => s.trim().to_owned(),
+    }
+}
+
+// values keep being output as values once changed, so escaping still
+// happens to them further along
+enum OutputPiece {
+    Text(String),
+    Value(String),
+    Raw(String),
+}
+
+// holds onto everything output inside a transform block until it ends,
+// since trimming needs to know where the output as a whole starts and ends
+struct TransformOutput<'a> {
+    inner: &'a mut dyn Outputter,
+    transform: OutputTransform,
+    pieces: Vec<OutputPiece>,
+}
+
+impl<'a> TransformOutput<'a> {
+    fn new(inner: &'a mut dyn Outputter, transform: OutputTransform) -> Self {
+        TransformOutput {
+            inner,
+            transform,
+            pieces: Vec::new(),
+        }
+    }
+
+    fn flush(&mut self) -> LangResult<()> {
+        let mut pieces = std::mem::take(&mut self.pieces);
+        if self.transform == OutputTransform::Trim {
+            for piece in pieces.iter_mut() {
+                let (OutputPiece::Text(s) | OutputPiece::Value(s) | OutputPiece::Raw(s)) = piece;
+                *s = s.trim_start().to_owned();
+                if !s.is_empty() {
+                    break;
+                }
+            }
+            for piece in pieces.iter_mut().rev() {
+                let (OutputPiece::Text(s) | OutputPiece::Value(s) | OutputPiece::Raw(s)) = piece;
+                s.truncate(s.trim_end().len());
+                if !s.is_empty() {
+                    break;
+                }
+            }
+        }
+        for piece in pieces {
+            match piece {
+                OutputPiece::Text(s) => self.inner.output_string(&s, None),
+                OutputPiece::Value(s) => self.inner.output_value(new_value(VarValues::Str(s)))?,
+                OutputPiece::Raw(s) => self.inner.output_value(new_value(VarValues::Raw(s)))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Outputter for TransformOutput<'_> {
+    fn output_string(&mut self, s: &str, _: Option<f64>) {
+        let s = match self.transform {
+            OutputTransform::Trim => s.to_owned(),
+            transform => apply_transform(transform, s),
+        };
+        self.pieces.push(OutputPiece::Text(s));
+    }
+
+    fn output_value(&mut self, v: Gc<VarValues>) -> LangResult<()> {
+        let piece = match &*borrow_val(&v)? {
+            VarValues::Nil => {
+                return Ok(());
+            },
+            VarValues::Raw(s) if self.transform == OutputTransform::Trim => OutputPiece::Raw(s.clone()),
+            VarValues::Raw(s) => OutputPiece::Raw(apply_transform(self.transform, s)),
+            val if self.transform == OutputTransform::Trim => OutputPiece::Value(val.to_string()),
+            val => OutputPiece::Value(apply_transform(self.transform, &val.to_string())),
+        };
+        self.pieces.push(piece);
+        Ok(())
+    }
+}
+
 pub struct CollectOutput {
     results: Vec<Gc<VarValues>>
 }
 
 impl Outputter for CollectOutput {
     fn output_string(&mut self, s: &str, v: Option<f64>) {
-        self.results.push(new_value(VarValues::AstStr(s.to_owned(), v)));
+        self.results.push(new_value(VarValues::AstStr(Rc::from(s), v)));
     }
 
     fn output_value(&mut self, v: Gc<VarValues>) -> LangResult<()> {
@@ -709,12 +1384,120 @@ impl Outputter for CollectOutput {
     }
 }
 
+// where a variable was last found when looked up from a given scope
+struct VarCacheEntry {
+    scope: Gc<Namespace>,
+    found_in: Gc<Namespace>,
+    epoch: usize,
+}
+
+// where each char starts in the string that was last indexed, so going
+// through a long string an index at a time doesn't walk it from the start
+// for every char; strings never change once made, so holding on to the
+// value is enough to know the offsets are still right
+struct StrIndexCache {
+    string: Gc<VarValues>,
+    // None when the string is all ascii, and a char is just a byte
+    offsets: Option<Vec<usize>>,
+}
+
+// strings shorter than this are quick enough to walk every time
+const STR_INDEX_CACHE_MIN_LEN: usize = 64;
+
+// numbers in this range are shared, rather than allocated each time one is made
+const SMALL_NUM_MIN: i32 = -128;
+const SMALL_NUM_MAX: i32 = 256;
+
+// values made often enough that it's worth handing out shared copies of them
+// this is only safe since nil, numbers and strings can't be modified in place
+struct ValueCache {
+    nil: Gc<VarValues>,
+    empty_str: Gc<VarValues>,
+    nums: Vec<Gc<VarValues>>,
+}
+
+impl ValueCache {
+    fn new() -> Self {
+        ValueCache {
+            nil: new_value(VarValues::Nil),
+            empty_str: new_value(VarValues::Str(String::new())),
+            nums: (SMALL_NUM_MIN..=SMALL_NUM_MAX)
+                .map(|n| new_value(VarValues::Num(n as f64)))
+                .collect(),
+        }
+    }
+    fn num(&self, n: f64) -> Gc<VarValues> {
+        // -0 prints differently to 0, so it can't share its value
+        if n.fract() == 0.0 && n >= SMALL_NUM_MIN as f64 && n <= SMALL_NUM_MAX as f64
+            && !(n == 0.0 && n.is_sign_negative()) {
+            Gc::clone(&self.nums[(n as i32 - SMALL_NUM_MIN) as usize])
+        } else {
+            new_value(VarValues::Num(n))
+        }
+    }
+    fn nil(&self) -> Gc<VarValues> {
+        Gc::clone(&self.nil)
+    }
+    fn str(&self, s: String) -> Gc<VarValues> {
+        if s.is_empty() {
+            Gc::clone(&self.empty_str)
+        } else {
+            new_value(VarValues::Str(s))
+        }
+    }
+}
+
+// xorshift gets stuck on a zero state, so the seed can never be zero
+const FIXED_SEED: u64 = 0x9E37_79B9_7F4A_7C15;
+
+fn clock_seed() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64);
+    nanos | 1
+}
+
 pub struct Context {
     pub stack: Vec<Gc<VarValues>>,
     loop_stack: Vec<LoopFrame>,
     cur_scope: Gc<Namespace>,
+    // changes whenever a name is added to or removed from any scope,
+    // which is the only time a cached lookup can go stale
+    scope_epoch: usize,
+    var_cache: HashMap<String, VarCacheEntry>,
+    str_index_cache: Option<StrIndexCache>,
+    values: ValueCache,
+    // whether builtins are allowed to touch the filesystem
+    pub fs_access: bool,
+    // files currently being imported, to catch circular imports
+    pub(crate) importing: Vec<PathBuf>,
+    // whether functions that don't use args throw when given too many
+    pub strict_args: bool,
+    // whether integer arithmetic throws once it's past what f64 holds exactly
+    pub int_checks: bool,
+    // whether every call to a user function is logged to stderr
+    pub trace_calls: bool,
+    // how many user functions are being run, one inside the other
+    pub(crate) call_depth: usize,
+    // whether to stop and wait for a command before each instruction
+    pub debug_stepping: bool,
+    // xorshift state for the builtins that pick things at random
+    rng_state: u64,
+    // whether anything that would vary between runs is made not to, with
+    // maps giving their keys in order and the random builtins seeded the same
+    deterministic: bool,
+    // run every so many instructions, so whatever is running the interpreter
+    // gets a turn in the middle of a long program, and can stop it with an error
+    yield_hook: Option<YieldHook>,
+    yield_interval: usize,
+    steps_until_yield: usize,
+    // set from anywhere, even another thread, to stop the program before
+    // its next instruction
+    cancel_token: Option<Arc<AtomicBool>>,
 }
 
+pub type YieldHook = Box<dyn FnMut(&mut Context) -> LangResult<()>>;
+
 fn concat_vals(values: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
     let mut filtered_values = Vec::with_capacity(values.len());
     for val in values {
@@ -750,7 +1533,8 @@ fn concat_vals(values: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
     }
 }
 
-fn set_scope_var(name: String, value: Gc<VarValues>, mut ns: Gc<Namespace>) -> LangResult<()> {
+// returns true if the name wasn't in scope before, and had to be added
+fn set_scope_var(name: String, value: Gc<VarValues>, mut ns: Gc<Namespace>) -> LangResult<bool> {
     loop {
         let cur_ns = Gc::clone(&ns);
         let mut ns_ref = borrow_val_mut(&cur_ns)?;
@@ -765,16 +1549,14 @@ fn set_scope_var(name: String, value: Gc<VarValues>, mut ns: Gc<Namespace>) -> L
             }
             Some(VarRefType::Value(v)) => {
                 *v = value;
-                break;
+                return Ok(false);
             }
             None => {
                 ns_ref.vars.insert(name, VarRefType::Value(value));
-                break;
+                return Ok(true);
             }
         }
     }
-
-    Ok(())
 }
 
 impl Context {
@@ -788,17 +1570,34 @@ impl Context {
         Context {
             stack: Vec::new(),
             loop_stack: Vec::new(),
-            cur_scope: global_scope
+            cur_scope: global_scope,
+            scope_epoch: 0,
+            var_cache: HashMap::new(),
+            str_index_cache: None,
+            values: ValueCache::new(),
+            fs_access: false,
+            importing: Vec::new(),
+            strict_args: false,
+            int_checks: false,
+            trace_calls: false,
+            call_depth: 0,
+            debug_stepping: false,
+            rng_state: clock_seed(),
+            deterministic: false,
+            yield_hook: None,
+            yield_interval: 0,
+            steps_until_yield: 0,
+            cancel_token: None,
         }
     }
     pub fn with_args(args: Vec<String>) -> Self {
         let mut global_vars = HashMap::new();
         register_builtins(&mut global_vars);
-        let args_var = new_value(VarValues::List(
+        let args_var = new_value(VarValues::List(Rc::new(
             args.into_iter()
                 .map(|s| new_value(VarValues::Str(s)))
                 .collect()
-        ));
+        )));
         global_vars.insert(String::from("args"), VarRefType::Value(args_var));
         let global_scope = new_value(Namespace {
             vars: global_vars,
@@ -807,18 +1606,283 @@ impl Context {
         Context {
             stack: Vec::new(),
             loop_stack: Vec::new(),
-            cur_scope: global_scope
+            cur_scope: global_scope,
+            scope_epoch: 0,
+            var_cache: HashMap::new(),
+            str_index_cache: None,
+            values: ValueCache::new(),
+            fs_access: false,
+            importing: Vec::new(),
+            strict_args: false,
+            int_checks: false,
+            trace_calls: false,
+            call_depth: 0,
+            debug_stepping: false,
+            rng_state: clock_seed(),
+            deterministic: false,
+            yield_hook: None,
+            yield_interval: 0,
+            steps_until_yield: 0,
+            cancel_token: None,
+        }
+    }
+    // the hook is called after every interval instructions, counted across
+    // every function call, and any error it gives is thrown from where the
+    // program had got to, as if the instruction had thrown it
+    pub fn set_yield_hook(&mut self, interval: usize, hook: YieldHook) {
+        let interval = interval.max(1);
+        self.yield_hook = Some(hook);
+        self.yield_interval = interval;
+        self.steps_until_yield = interval;
+    }
+    // storing true in the token throws <cancelled> from wherever the program
+    // has got to, which catch passes on rather than catching, so the program
+    // can't carry on past it
+    pub fn cancel_token(&mut self) -> Arc<AtomicBool> {
+        Arc::clone(self.cancel_token.get_or_insert_with(|| Arc::new(AtomicBool::new(false))))
+    }
+    fn is_cancelled(&self) -> bool {
+        match &self.cancel_token {
+            Some(token) => token.load(Ordering::Relaxed),
+            None => false,
+        }
+    }
+    fn check_cancelled(&self) -> LangResult<()> {
+        if self.is_cancelled() {
+            return throw_string!("<cancelled>");
         }
+        Ok(())
+    }
+    pub fn define_global(&mut self, name: String, value: Gc<VarValues>) -> LangResult<()> {
+        if set_scope_var(name, value, Gc::clone(&self.cur_scope))? {
+            self.scope_epoch += 1;
+        }
+        Ok(())
+    }
+    // gives the program a global made from rust data, such as a config map
+    pub fn bind<T: IntoVarValues>(&mut self, name: &str, value: T) -> LangResult<()> {
+        self.define_global(name.to_owned(), value.into_varvalues())
+    }
+    // lets whatever is running the interpreter add builtins of its own before
+    // running a program, which it can shadow or delete like any other
+    pub fn register(&mut self, name: &str, func: RustFn) -> LangResult<()> {
+        self.define_global(name.to_owned(), new_value(VarValues::RustFunc(func)))
+    }
+    // the same, for a builtin that needs to hold on to state of its own
+    pub fn register_closure<F>(&mut self, name: &str, func: F) -> LangResult<()>
+    where
+        F: Fn(&mut Context, Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> + 'static
+    {
+        self.define_global(name.to_owned(), new_value(VarValues::RustClosure(Box::new(func))))
+    }
+    pub fn new_num(&self, n: f64) -> Gc<VarValues> {
+        self.values.num(n)
+    }
+    pub fn new_nil(&self) -> Gc<VarValues> {
+        self.values.nil()
+    }
+    pub fn new_str(&self, s: String) -> Gc<VarValues> {
+        self.values.str(s)
+    }
+    pub fn make_deterministic(&mut self) {
+        self.deterministic = true;
+        self.rng_state = FIXED_SEED;
+    }
+    // a map's keys, in whatever order it iterates in unless runs have to
+    // be reproducible
+    pub(crate) fn map_keys<'a, T>(&self, map: &'a HashMap<String, T>) -> Vec<&'a String> {
+        let mut keys: Vec<&String> = map.keys().collect();
+        if self.deterministic {
+            keys.sort();
+        }
+        keys
+    }
+    // indexes long strings through str_index_cache, or gives None for anything
+    // that should go through get_index as usual
+    fn index_str_cached(&mut self, obj: &Gc<VarValues>, index: &Gc<VarValues>) -> LangResult<Option<Gc<VarValues>>> {
+        let obj_ref = borrow_val(obj)?;
+        let s = match obj_ref.as_str() {
+            Some(s) if s.len() >= STR_INDEX_CACHE_MIN_LEN => s,
+            _ => return Ok(None),
+        };
+        let index = match &*borrow_val(index)? {
+            VarValues::Str(i) => string_to_f64(i),
+            VarValues::Num(n) |
+            VarValues::AstStr(_, Some(n)) => Some(*n),
+            _ => None,
+        };
+        let index = match index {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+        let cached = match &self.str_index_cache {
+            Some(cache) => std::ptr::eq(&*cache.string, &**obj),
+            None => false,
+        };
+        if !cached {
+            let offsets = match s.is_ascii() {
+                true => None,
+                false => Some(s.char_indices().map(|(i, _)| i).collect()),
+            };
+            self.str_index_cache = Some(StrIndexCache {
+                string: *obj,
+                offsets,
+            });
+        }
+        let (start, end) = match &self.str_index_cache.as_ref().unwrap().offsets {
+            Some(offsets) => {
+                let i = validate_list_index(index, offsets.len())?;
+                (offsets[i], offsets.get(i + 1).copied().unwrap_or(s.len()))
+            },
+            None => {
+                let i = validate_list_index(index, s.len())?;
+                (i, i + 1)
+            },
+        };
+        Ok(Some(self.new_str(s[start..end].to_owned())))
+    }
+    // a random number in 0..n, which has to be non-zero
+    pub(crate) fn random_below(&mut self, n: usize) -> usize {
+        // xorshift64*, which is plenty for picking list items
+        let mut x = self.rng_state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.rng_state = x;
+        (x.wrapping_mul(0x2545_F491_4F6C_DD1D) % n as u64) as usize
+    }
+    // runs a separately compiled program in the current scope, returning its
+    // output as a value instead of writing it out
+    pub fn run_collected(&mut self, prog: &[Instruction]) -> LangResult<Gc<VarValues>> {
+        let mut collector = CollectOutput {
+            results: Vec::new(),
+        };
+        self.interpret(prog, &mut collector)?;
+        concat_vals(collector.results)
+    }
+    // every name in scope that isn't bound to a builtin, which code compiled
+    // to run in this scope can't assume anything about
+    // the user functions in the current scope with names starting with prefix,
+    // in order of name
+    pub fn functions_named(&self, prefix: &str) -> LangResult<Vec<(String, Gc<VarValues>)>> {
+        let mut funcs = Vec::new();
+        for (name, var) in &borrow_val(&self.cur_scope)?.vars {
+            if let VarRefType::Value(v) = var {
+                if name.starts_with(prefix) && matches!(&*borrow_val(v)?, VarValues::Func(..)) {
+                    funcs.push((name.clone(), Gc::clone(v)));
+                }
+            }
+        }
+        funcs.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(funcs)
+    }
+    pub(crate) fn rebound_names(&self) -> LangResult<Vec<String>> {
+        let mut names = Vec::new();
+        let mut ns = Some(Gc::clone(&self.cur_scope));
+        while let Some(cur_ns) = ns {
+            let ns_ref = borrow_val(&cur_ns)?;
+            for (name, var) in &ns_ref.vars {
+                if let VarRefType::Value(v) = var {
+                    if !matches!(&*borrow_val(v)?, VarValues::RustFunc(_)) {
+                        names.push(name.clone());
+                    }
+                }
+            }
+            ns = Option::clone(&ns_ref.outer_scope);
+        }
+        Ok(names)
+    }
+    // runs a function body while logging its args, and what it returned or threw,
+    // with a "| " for each call it's inside of, so the nesting shows even
+    // where leading whitespace would be lost
+    fn interpret_traced(&mut self, prog: &[Instruction], name: &str, args_text: String, outputter: &mut dyn Outputter) -> LangResult<()> {
+        let indent = "| ".repeat(self.call_depth - 1);
+        eprintln!("{}call {}({})", indent, name, args_text);
+        // the output is collected, so the return value can be shown before it's passed on
+        let mut collector = CollectOutput {
+            results: Vec::new(),
+        };
+        let ret = self.interpret(prog, &mut collector);
+        match ret {
+            Ok(_) => {
+                let val = concat_vals(collector.results)?;
+                eprintln!("{}return {} {}", indent, name, borrow_val(&val)?.to_string());
+                outputter.output_value(val)
+            },
+            Err(LangError::Throw(v)) => {
+                eprintln!("{}throw {} {}", indent, name, borrow_val(&v)?.to_string());
+                Err(LangError::Throw(v))
+            },
+            Err(e) => Err(e),
+        }
+    }
+    pub fn call_value(&mut self, func: &Gc<VarValues>, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+        let mut collector = CollectOutput {
+            results: Vec::new(),
+        };
+        borrow_val(func)?.call(self, args, &mut collector)?;
+        concat_vals(collector.results)
     }
     #[inline]
-    fn interpret_inst(&mut self, prog: &[Instruction], counter: &mut usize, outputter: &mut dyn Outputter) -> LangResult<()> {
+    fn get_var(&mut self, name: &str) -> LangResult<Gc<VarValues>> {
+        if let Some(entry) = self.var_cache.get(name) {
+            if entry.epoch == self.scope_epoch && std::ptr::eq(&*entry.scope, &*self.cur_scope) {
+                if let Some(VarRefType::Value(v)) = borrow_val(&entry.found_in)?.vars.get(name) {
+                    return Ok(Gc::clone(v));
+                }
+            }
+        }
+
+        let mut ns = Gc::clone(&self.cur_scope);
+        loop {
+            let cur_ns = Gc::clone(&ns);
+            let ns_ref = borrow_val(&cur_ns)?;
+            match ns_ref.vars.get(name) {
+                Some(VarRefType::Value(v)) => {
+                    let var_value = Gc::clone(v);
+                    // the entry holds on to the scope it was looked up from, so that
+                    // scope can't be freed and have its address reused by another
+                    self.var_cache.insert(name.to_owned(), VarCacheEntry {
+                        scope: Gc::clone(&self.cur_scope),
+                        found_in: cur_ns,
+                        epoch: self.scope_epoch,
+                    });
+                    return Ok(var_value);
+                }
+                Some(VarRefType::NonLocal) | None => match &ns_ref.outer_scope {
+                    Some(new_ns) => {
+                        ns = Gc::clone(new_ns);
+                    }
+                    None => {
+                        return throw_string!("<{}:unknown var>", name);
+                    }
+                }
+            }
+        }
+    }
+    // where the top n values of the stack start, throwing rather than
+    // panicking if malformed bytecode has left fewer than that on it
+    fn stack_start(&self, n: usize) -> LangResult<usize> {
+        match self.stack.len().checked_sub(n) {
+            Some(start) => Ok(start),
+            None => throw_string!("<internal error: stack underflow>"),
+        }
+    }
+    fn pop_stack(&mut self) -> LangResult<Gc<VarValues>> {
+        match self.stack.pop() {
+            Some(v) => Ok(v),
+            None => throw_string!("<internal error: stack underflow>"),
+        }
+    }
+    // the top n values, in the order they were pushed
+    fn pop_stack_n(&mut self, n: usize) -> LangResult<Vec<Gc<VarValues>>> {
+        let start = self.stack_start(n)?;
+        Ok(self.stack.split_off(start))
+    }
+    fn interpret_inst(&mut self, prog: &[Instruction], counter: &mut usize, outputter: &mut dyn Outputter) -> LangResult<InstFlow> {
         match &prog[*counter] {
             Instruction::PUSHSTR(s) => {
-                self.stack.push(
-                    new_value(
-                        VarValues::Str(s.clone())
-                    )
-                );
+                self.stack.push(self.values.str(s.clone()));
             },
             Instruction::PUSHASTSTR(s, v) => {
                 self.stack.push(
@@ -828,115 +1892,158 @@ impl Context {
                 );
             },
             Instruction::PUSHNIL => {
-                self.stack.push(new_value(VarValues::Nil));
+                self.stack.push(self.values.nil());
             },
             Instruction::PUSHNUM(n) => {
-                self.stack.push(new_value(VarValues::Num(*n)));
+                self.stack.push(self.values.num(*n));
             }
             Instruction::OUTPUTSTR(s, v) => {
                 outputter.output_string(s, *v);
             }
             Instruction::OUTPUTVAL => {
-                let val = self.stack.pop().unwrap();
+                let val = self.pop_stack()?;
                 outputter.output_value(val)?;
             }
             Instruction::IFFALSE(i) => {
-                let test: bool = (&*borrow_val(&self.stack.pop().unwrap())?).into();
+                let test: bool = (&*borrow_val(&self.pop_stack()?)?).into();
                 if !test {
                     *counter = *i;
-                    return Ok(());
+                    return Ok(InstFlow::Next);
                 }
             },
             Instruction::GOTO(i) => {
                 *counter = *i;
-                return Ok(());
+                return Ok(InstFlow::Next);
+            },
+            Instruction::IFNOTTYPE(name, i) => {
+                // the value stays for the next test, until a branch is taken
+                let top = &self.stack[self.stack_start(1)?];
+                if borrow_val(top)?.type_name() != name {
+                    *counter = *i;
+                    return Ok(InstFlow::Next);
+                }
             },
             Instruction::CONCAT(n) => {
                 let n = *n;
                 if n >= 2 {
-                    let concat_val = concat_vals(self.stack.split_off(self.stack.len() - n))?;
+                    let concat_val = concat_vals(self.pop_stack_n(n)?)?;
                     self.stack.push(concat_val);
                 }
             },
             Instruction::DROP(n) => {
-                self.stack.truncate(self.stack.len() - *n);
+                let start = self.stack_start(*n)?;
+                self.stack.truncate(start);
+            },
+            Instruction::DUP(n) => {
+                let start = self.stack_start(*n)?;
+                self.stack.extend_from_within(start..);
+            },
+            Instruction::SWAP => {
+                let start = self.stack_start(2)?;
+                self.stack.swap(start, start + 1);
             },
             Instruction::SETVAR(name) => {
-                let value = self.stack.pop().unwrap();
-                set_scope_var(name.clone(), value, Gc::clone(&self.cur_scope))?;
+                let value = self.pop_stack()?;
+                if set_scope_var(name.clone(), value, Gc::clone(&self.cur_scope))? {
+                    self.scope_epoch += 1;
+                }
             },
             Instruction::SETATTR => {
-                let val = self.stack.pop().unwrap();
-                let index = self.stack.pop().unwrap();
-                let obj = self.stack.pop().unwrap();
+                let val = self.pop_stack()?;
+                let index = self.pop_stack()?;
+                let obj = self.pop_stack()?;
                 let obj_clone = Gc::clone(&obj);
                 borrow_val_mut(&obj)?.set_attr(obj_clone, index, val)?;
             },
             Instruction::SETINDEX => {
-                let val = self.stack.pop().unwrap();
-                let index = self.stack.pop().unwrap();
-                let obj = self.stack.pop().unwrap();
+                let val = self.pop_stack()?;
+                let index = self.pop_stack()?;
+                let obj = self.pop_stack()?;
                 let obj_clone = Gc::clone(&obj);
                 borrow_val_mut(&obj)?.set_index(obj_clone, index, val)?;
             },
             Instruction::SETNONLOCAL(name) => {
                 borrow_val_mut(&self.cur_scope)?.vars.insert(name.clone(), VarRefType::NonLocal);
+                self.scope_epoch += 1;
+            },
+            Instruction::PUSHSCOPE(names) => {
+                let vals = self.pop_stack_n(names.len())?;
+                let vars = names.iter()
+                    .cloned()
+                    .zip(vals.into_iter().map(VarRefType::Value))
+                    .collect();
+                self.cur_scope = new_value(Namespace {
+                    vars,
+                    outer_scope: Some(Gc::clone(&self.cur_scope)),
+                });
             },
-            Instruction::GETVAR(name) => {
-                let mut ns = Gc::clone(&self.cur_scope);
-                let var_value;
-                loop {
-                    let cur_ns = Gc::clone(&ns);
-                    let ns_ref = borrow_val(&cur_ns)?;
-                    match ns_ref.vars.get(name) {
-                        Some(VarRefType::Value(v)) => {
-                            var_value = Gc::clone(v);
-                            break;
+            Instruction::POPSCOPE(n) => {
+                for _ in 0..*n {
+                    let outer = match &borrow_val(&self.cur_scope)?.outer_scope {
+                        Some(outer) => Gc::clone(outer),
+                        None => {
+                            return throw_string!("<internal error: no scope to leave>");
                         }
-                        Some(VarRefType::NonLocal) | None => match &ns_ref.outer_scope {
-                            Some(new_ns) => {
-                                ns = Gc::clone(new_ns);
-                            }
-                            None => {
-                                //println!("not found");
-                                return throw_string!("<{}:unknown var>", name);
-                            }
-                        }
-                    }
+                    };
+                    self.cur_scope = outer;
                 }
+            },
+            Instruction::GETVAR(name) => {
+                let var_value = self.get_var(name)?;
                 self.stack.push(var_value);
             },
             Instruction::GETATTR => {
-                let index = self.stack.pop().unwrap();
-                let obj = self.stack.pop().unwrap();
+                let index = self.pop_stack()?;
+                let obj = self.pop_stack()?;
                 let obj_clone = Gc::clone(&obj);
-                self.stack.push(borrow_val(&obj)?.get_attr(obj_clone, index)?);
+                self.stack.push(borrow_val(&obj)?.get_attr(self, obj_clone, index)?);
             },
             Instruction::GETINDEX => {
-                let index = self.stack.pop().unwrap();
-                let obj = self.stack.pop().unwrap();
-                let obj_clone = Gc::clone(&obj);
-                self.stack.push(borrow_val(&obj)?.get_index(obj_clone, index)?);
+                let index = self.pop_stack()?;
+                let obj = self.pop_stack()?;
+                let val = match self.index_str_cached(&obj, &index)? {
+                    Some(val) => val,
+                    None => {
+                        let obj_clone = Gc::clone(&obj);
+                        let val = borrow_val(&obj)?.get_index(obj_clone, index)?;
+                        val
+                    }
+                };
+                self.stack.push(val);
+            },
+            Instruction::GETSLICE => {
+                let step = self.pop_stack()?;
+                let end = self.pop_stack()?;
+                let start = self.pop_stack()?;
+                let obj = self.pop_stack()?;
+                self.stack.push(borrow_val(&obj)?.get_slice(start, end, step)?);
             },
             Instruction::DELVAR(name) => {
                 borrow_val_mut(&self.cur_scope)?.vars.remove(name);
+                self.scope_epoch += 1;
             },
             Instruction::DELATTR => {
-                let index = self.stack.pop().unwrap();
-                let obj = self.stack.pop().unwrap();
+                let index = self.pop_stack()?;
+                let obj = self.pop_stack()?;
                 borrow_val_mut(&obj)?.del_attr(index)?;
             },
             Instruction::DELINDEX => {
-                let index = self.stack.pop().unwrap();
-                let obj = self.stack.pop().unwrap();
+                let index = self.pop_stack()?;
+                let obj = self.pop_stack()?;
                 borrow_val_mut(&obj)?.del_index(index)?;
             },
-            Instruction::CREATEFUNC(arg_names, loc, size) => {
+            // functions close over the scope they're made in, rather than the values
+            // in it, so they see any later change to a variable, and functions made in
+            // the same scope share its variables; {let:x:{x}:{lambda:...;};} gives one
+            // a copy of x that's its own
+            Instruction::CREATEFUNC(name, arg_names, uses_args, loc, size) => {
                 let loc = *loc;
                 let size = *size;
                 self.stack.push(
                     new_value(VarValues::Func(
+                        name.clone(),
                         arg_names.clone(),
+                        *uses_args,
                         prog[loc..loc+size].to_vec(),
                         Gc::clone(&self.cur_scope)
                     ))
@@ -944,28 +2051,82 @@ impl Context {
             },
             Instruction::CALLFUNC(arg_size, direct_output) => {
                 let arg_size = *arg_size;
-                let args = self.stack.split_off(self.stack.len() - arg_size);
-                let called_var = self.stack.pop().unwrap();
+                let args = self.pop_stack_n(arg_size)?;
+                let called_var = self.pop_stack()?;
                 if *direct_output {
                     borrow_val(&called_var)?.call(self, args, outputter)?;
                 } else {
-                    let mut collector = CollectOutput {
-                        results: Vec::new(),
-                    };
-                    borrow_val(&called_var)?.call(self, args, &mut collector)?;
-                    self.stack.push(concat_vals(collector.results)?);
+                    let ret = self.call_value(&called_var, args)?;
+                    self.stack.push(ret);
+                }
+            },
+            Instruction::CALLSPREAD(spread, direct_output) => {
+                let mut args = Vec::with_capacity(spread.len());
+                for (arg, spread) in self.pop_stack_n(spread.len())?.into_iter().zip(spread) {
+                    if !*spread {
+                        args.push(arg);
+                        continue;
+                    }
+                    match &*borrow_val(&arg)? {
+                        VarValues::List(vals) => args.extend(vals.iter().copied()),
+                        _ => {
+                            return throw_string!("<spread:expected list>");
+                        }
+                    }
+                }
+                let called_var = self.pop_stack()?;
+                if *direct_output {
+                    borrow_val(&called_var)?.call(self, args, outputter)?;
+                } else {
+                    let ret = self.call_value(&called_var, args)?;
+                    self.stack.push(ret);
                 }
             },
             Instruction::CREATELIST(n) => {
-                let vals = self.stack.split_off(self.stack.len() - n);
+                let vals = self.pop_stack_n(*n)?;
                 self.stack.push(
                     new_value(
-                        VarValues::List(vals)
+                        VarValues::List(Rc::new(vals))
                     )
                 );
             },
+            Instruction::UNPACKLIST(n) => {
+                let list = self.pop_stack()?;
+                match &*borrow_val(&list)? {
+                    VarValues::List(vals) if vals.len() == *n => {
+                        self.stack.extend(vals.iter().copied());
+                    },
+                    VarValues::List(vals) => {
+                        return throw_string!("<set:expected {} items, got {}>", n, vals.len());
+                    },
+                    _ => {
+                        return throw_string!("<set:expected list>");
+                    }
+                };
+            },
+            Instruction::UNPACKMAP(keys) => {
+                let map = self.pop_stack()?;
+                match &*borrow_val(&map)? {
+                    VarValues::Map(vals) => {
+                        for key in keys {
+                            match vals.get(key) {
+                                Some(v) => self.stack.push(*v),
+                                None => return throw_string!("<set:{}:unknown key>", key),
+                            }
+                        }
+                    },
+                    _ => {
+                        return throw_string!("<set:expected map>");
+                    }
+                };
+            },
             Instruction::CREATEMAP(n) => {
-                let vals = self.stack.split_off(self.stack.len() - n);
+                // the map form checks this when compiling, but bytecode
+                // built any other way has nothing to guarantee it
+                if n % 2 != 0 {
+                    return throw_string!("<map:odd number of values>");
+                }
+                let vals = self.pop_stack_n(*n)?;
                 let mut map = HashMap::with_capacity(n/2);
                 let mut iter = vals.into_iter();
                 for _ in 0..n/2 {
@@ -975,7 +2136,7 @@ impl Context {
                 }
                 self.stack.push(
                     new_value(
-                        VarValues::Map(map)
+                        VarValues::Map(Rc::new(map))
                     )
                 );
             },
@@ -986,13 +2147,20 @@ impl Context {
                 });
             },
             Instruction::FORSTART(ident) => {
-                let step = val_to_f64(&self.stack.pop().unwrap(), "for")?;
-                let end = val_to_f64(&self.stack.pop().unwrap(), "for")?;
-                let start = val_to_f64(&self.stack.pop().unwrap(), "for")?;
+                let step = val_to_f64(&self.pop_stack()?, "for")?;
+                let end = val_to_f64(&self.pop_stack()?, "for")?;
+                let start = val_to_f64(&self.pop_stack()?, "for")?;
                 if step == 0.0 {
                     return throw_string!("<for:zero-size step>");
                 }
-                set_scope_var(ident.clone(), new_value(VarValues::Num(start)), Gc::clone(&self.cur_scope))?;
+                // a start already at the end is just a loop that runs no times,
+                // but a step away from the end is always a mistake
+                if (end - start) * step < 0.0 {
+                    return throw_string!("<for:step direction cannot reach end>");
+                }
+                if set_scope_var(ident.clone(), self.values.num(start), Gc::clone(&self.cur_scope))? {
+                    self.scope_epoch += 1;
+                }
                 self.loop_stack.push(LoopFrame {
                     stack_vals: 0,
                     loop_data: LoopType::For {
@@ -1008,10 +2176,10 @@ impl Context {
                     LoopType::For {value, step, end, ..} => {
                         if step > 0.0 && value >= end {
                             *counter = *jump;
-                            return Ok(());
+                            return Ok(InstFlow::Next);
                         } else if step < 0.0 && value <= end {
                             *counter = *jump;
-                            return Ok(());
+                            return Ok(InstFlow::Next);
                         }
                     }
                     _ => {
@@ -1023,7 +2191,9 @@ impl Context {
                 match &mut self.loop_stack.last_mut().unwrap().loop_data {
                     LoopType::For {ident, value, step, ..} => {
                         *value += *step;
-                        set_scope_var(ident.clone(), new_value(VarValues::Num(*value)), Gc::clone(&self.cur_scope))?;
+                        if set_scope_var(ident.clone(), self.values.num(*value), Gc::clone(&self.cur_scope))? {
+                            self.scope_epoch += 1;
+                        }
                     }
                     _ => {
                         panic!("invalid loop type in FORTEST");
@@ -1031,11 +2201,39 @@ impl Context {
                 }
             },
             Instruction::FOREACHSTART(ident) => {
-                let list = self.stack.pop().unwrap();
+                let list = self.pop_stack()?;
                 let list_ref = borrow_val(&list)?;
                 match &*list_ref {
-                    VarValues::List(vals) => {
-                        set_scope_var(ident.clone(), Gc::clone(&vals[0]), Gc::clone(&self.cur_scope))?;
+                    VarValues::List(_) => {
+                        // FOREACHITER assigns the first item, so empty lists
+                        // simply skip the loop body
+                    }
+                    VarValues::Range(start, end, step) => {
+                        // ranges are stepped through directly, without
+                        // creating a value for every item up front
+                        self.loop_stack.push(LoopFrame {
+                            stack_vals: 0,
+                            loop_data: LoopType::ForEachRange {
+                                ident: ident.clone(),
+                                start: *start,
+                                step: *step,
+                                index: 0,
+                                len: range_len(*start, *end, *step),
+                            },
+                        });
+                        *counter += 1;
+                        return Ok(InstFlow::Next);
+                    }
+                    VarValues::Iterator(_) => {
+                        self.loop_stack.push(LoopFrame {
+                            stack_vals: 0,
+                            loop_data: LoopType::ForEachIter {
+                                ident: ident.clone(),
+                                iter: Gc::clone(&list),
+                            },
+                        });
+                        *counter += 1;
+                        return Ok(InstFlow::Next);
                     }
                     _ => {
                         return throw_string!("<foreach:invalid iter type>");
@@ -1066,14 +2264,51 @@ impl Context {
                             VarValues::List(vals) => {
                                 if vals.len() == *list_index {
                                     *counter = *jump;
+                                    return Ok(InstFlow::Next);
                                 } else {
-                                    set_scope_var(ident.clone(), Gc::clone(&vals[*list_index]), Gc::clone(&self.cur_scope))?;
+                                    if set_scope_var(ident.clone(), Gc::clone(&vals[*list_index]), Gc::clone(&self.cur_scope))? {
+                                        self.scope_epoch += 1;
+                                    }
                                     *list_index += 1;
                                 }
                             }
                             _ => unreachable!()
                         }
                     }
+                    LoopType::ForEachRange {ident, start, step, index, len} => {
+                        if *index == *len {
+                            *counter = *jump;
+                            return Ok(InstFlow::Next);
+                        } else {
+                            // computed the same way as range, so float steps don't drift
+                            let value = *start + *index as f64 * *step;
+                            if set_scope_var(ident.clone(), self.values.num(value), Gc::clone(&self.cur_scope))? {
+                                self.scope_epoch += 1;
+                            }
+                            *index += 1;
+                        }
+                    }
+                    LoopType::ForEachIter {ident, iter} => {
+                        let ident = ident.clone();
+                        let iter = Gc::clone(iter);
+                        // the iterator stays borrowed while it runs, so it can't
+                        // be used from inside of itself
+                        let next = match &mut *borrow_val_mut(&iter)? {
+                            VarValues::Iterator(f) => f(self)?,
+                            _ => unreachable!(),
+                        };
+                        match next {
+                            Some(val) => {
+                                if set_scope_var(ident, val, Gc::clone(&self.cur_scope))? {
+                                    self.scope_epoch += 1;
+                                }
+                            },
+                            None => {
+                                *counter = *jump;
+                                return Ok(InstFlow::Next);
+                            },
+                        }
+                    }
                     _ => {
                         panic!("invalid loop type in FOREACHTEST");
                     }
@@ -1087,13 +2322,13 @@ impl Context {
                     let n = self.loop_stack.pop().unwrap().stack_vals;
                     match n {
                         0 => {
-                            self.stack.push(new_value(VarValues::Nil));
+                            self.stack.push(self.values.nil());
                         },
                         1 => {
                             // no concat necessary
                         },
                         _ => {
-                            let concat_val = concat_vals(self.stack.split_off(self.stack.len() - n))?;
+                            let concat_val = concat_vals(self.pop_stack_n(n)?)?;
                             self.stack.push(concat_val);
                         },
                     }
@@ -1101,29 +2336,40 @@ impl Context {
                     self.loop_stack.pop();
                 }
             },
+            Instruction::LOOPENDLIST => {
+                let n = self.loop_stack.pop().unwrap().stack_vals;
+                let vals = self.pop_stack_n(n)?;
+                self.stack.push(new_value(VarValues::List(Rc::new(vals))));
+            },
             Instruction::STARTCATCH(loc) => {
                 let stack_size = self.stack.len();
                 let loop_stack_size = self.loop_stack.len();
+                // a throw out of a let leaves without running its POPSCOPE
+                let scope = Gc::clone(&self.cur_scope);
                 *counter += 1;
                 match self.catch_block(prog, outputter, counter) {
                     Ok(_) => {
-                        let top_val = self.stack.pop().unwrap();
+                        let top_val = self.pop_stack()?;
                         self.stack.push(
                             new_value(
                                 VarValues::CatchResult(true, top_val)
                             )
                         );
                     },
+                    Err(LangError::Throw(err_val)) if self.is_cancelled() => {
+                        return Err(LangError::Throw(err_val));
+                    },
                     Err(LangError::Throw(err_val)) => {
                         self.stack.truncate(stack_size);
                         self.loop_stack.truncate(loop_stack_size);
+                        self.cur_scope = scope;
                         self.stack.push(
                             new_value(
                                 VarValues::CatchResult(false, err_val)
                             )
                         );
                         *counter = *loc;
-                        return Ok(());
+                        return Ok(InstFlow::Next);
                     },
                     Err(LangError::CatchUnwind(0)) => {
                         // unwind hit floor, no value is created, continue as normal
@@ -1138,33 +2384,147 @@ impl Context {
                 return Err(LangError::CatchUnwind(*n));
             }
             Instruction::THROWVAL => {
-                let v = self.stack.pop().unwrap();
+                let v = self.pop_stack()?;
                 return Err(LangError::Throw(v));
             },
-            Instruction::END | Instruction::ENDCATCH => unimplemented!(),
+            Instruction::UNWRAPCATCH => {
+                let res = self.pop_stack()?;
+                let (success, v) = match &*borrow_val(&res)? {
+                    VarValues::CatchResult(success, v) => (*success, Gc::clone(v)),
+                    _ => {
+                        panic!("invalid value in UNWRAPCATCH");
+                    }
+                };
+                if !success {
+                    return Err(LangError::Throw(v));
+                }
+                self.stack.push(v);
+            },
+            Instruction::STARTTRANSFORM(transform, direct) => {
+                *counter += 1;
+                let mut transform_output = TransformOutput::new(outputter, *transform);
+                let res = self.catch_block(prog, &mut transform_output, counter);
+                if res.is_ok() {
+                    let val = self.pop_stack()?;
+                    // a block being output has already output everything itself
+                    if !*direct {
+                        // only text is changed when the block is used as a value,
+                        // anything else is given back as it is
+                        let changed = match &*borrow_val(&val)? {
+                            VarValues::Raw(s) => Some(VarValues::Raw(apply_transform(*transform, s))),
+                            v @ VarValues::Str(_) |
+                            v @ VarValues::AstStr(..) |
+                            v @ VarValues::Num(_) => Some(VarValues::Str(apply_transform(*transform, &v.to_string()))),
+                            _ => None,
+                        };
+                        self.stack.push(match changed {
+                            Some(v) => new_value(v),
+                            None => val,
+                        });
+                    }
+                }
+                // whatever was output before a throw still goes out, the same
+                // as it would without the transform
+                transform_output.flush()?;
+                match res {
+                    Ok(_) | Err(LangError::CatchUnwind(0)) => {},
+                    Err(LangError::CatchUnwind(n)) => {
+                        return Err(LangError::CatchUnwind(n-1));
+                    },
+                    Err(e) => {
+                        return Err(e);
+                    },
+                }
+            },
+            Instruction::CATCHKIND => {
+                let res = self.pop_stack()?;
+                let kind = self.pop_stack()?;
+                let kind = borrow_val(&kind)?.to_string();
+                if let VarValues::CatchResult(false, v) = &*borrow_val(&res)? {
+                    if error_kind(&*borrow_val(v)?).as_deref() != Some(&kind[..]) {
+                        return Err(LangError::Throw(Gc::clone(v)));
+                    }
+                }
+                self.stack.push(res);
+            },
+            Instruction::END => {
+                return Ok(InstFlow::End);
+            },
+            Instruction::ENDCATCH => {
+                return Ok(InstFlow::EndCatch);
+            },
         }
         *counter += 1;
-        Ok(())
+        Ok(InstFlow::Next)
+    }
+    // shows the next instruction, then reads commands from stdin until told to run it
+    // runs to the end without stopping again on continue, or once stdin is closed
+    fn debug_prompt(&mut self, prog: &[Instruction], counter: usize) -> LangResult<()> {
+        let top = match self.stack.last() {
+            Some(v) => borrow_val(v)?.to_string(),
+            None => String::from("<empty>"),
+        };
+        eprintln!("{:<2} - {:?} (top: {})", counter, prog[counter], top);
+        loop {
+            let mut line = String::new();
+            match std::io::stdin().read_line(&mut line) {
+                Ok(0) | Err(_) => {
+                    self.debug_stepping = false;
+                    return Ok(());
+                },
+                Ok(_) => {},
+            }
+            match line.trim() {
+                "" | "s" | "step" => {
+                    return Ok(());
+                },
+                "c" | "continue" => {
+                    self.debug_stepping = false;
+                    return Ok(());
+                },
+                "p" | "stack" => {
+                    for (i, v) in self.stack.iter().enumerate() {
+                        eprintln!("  {:<2} - {}", i, borrow_val(v)?.to_string());
+                    }
+                },
+                cmd => {
+                    eprintln!("unknown command '{}', expected step, continue or stack", cmd);
+                },
+            }
+        }
+    }
+    fn yield_to_host(&mut self) -> LangResult<()> {
+        self.steps_until_yield -= 1;
+        if self.steps_until_yield > 0 {
+            return Ok(());
+        }
+        self.steps_until_yield = self.yield_interval;
+        // the hook is taken out while it runs, since it gets the whole context
+        let mut hook = self.yield_hook.take().unwrap();
+        let ret = hook(self);
+        self.yield_hook = Some(hook);
+        ret
     }
     fn catch_block(&mut self, prog: &[Instruction], outputter: &mut dyn Outputter, counter: &mut usize) -> LangResult<()> {
         loop {
             //println!("stack: {:?}", self.stack);
             //println!("instr: {}, {:?}", *counter, prog[*counter]);
-            match &prog[*counter] {
-                Instruction::ENDCATCH => {
-                    break;
-                },
-                Instruction::END => {
+            if self.debug_stepping {
+                self.debug_prompt(prog, *counter)?;
+            }
+            if self.yield_hook.is_some() {
+                self.yield_to_host()?;
+            }
+            self.check_cancelled()?;
+            match self.interpret_inst(prog, counter, outputter) {
+                Ok(InstFlow::Next) => {}
+                Ok(InstFlow::EndCatch) => break,
+                Ok(InstFlow::End) => {
                     panic!("found end inside of catch block")
                 }
-                _ => {
-                    match self.interpret_inst(prog, counter, outputter) {
-                        Ok(()) => {}
-                        Err(LangError::Throw(v)) => return Err(LangError::Throw(v)),
-                        Err(LangError::CatchUnwind(0)) => return Err(LangError::CatchUnwind(0)),
-                        Err(LangError::CatchUnwind(n)) => return Err(LangError::CatchUnwind(n-1)),
-                    }
-                }
+                Err(LangError::Throw(v)) => return Err(LangError::Throw(v)),
+                Err(LangError::CatchUnwind(0)) => return Err(LangError::CatchUnwind(0)),
+                Err(LangError::CatchUnwind(n)) => return Err(LangError::CatchUnwind(n-1)),
             }
         }
         Ok(())
@@ -1174,23 +2534,24 @@ impl Context {
         loop {
             //println!("stack: {:?}", self.stack);
             //println!("instr: {}, {:?}", counter, prog[counter]);
-            match &prog[counter] {
-                Instruction::END => {
-                    break;
-                },
-                Instruction::ENDCATCH => {
+            if self.debug_stepping {
+                self.debug_prompt(prog, counter)?;
+            }
+            if self.yield_hook.is_some() {
+                self.yield_to_host()?;
+            }
+            self.check_cancelled()?;
+            match self.interpret_inst(prog, &mut counter, outputter) {
+                Ok(InstFlow::Next) => {}
+                Ok(InstFlow::End) => break,
+                Ok(InstFlow::EndCatch) => {
                     panic!("found endcatch outside of catch block");
                 },
-                _ => {
-                    match self.interpret_inst(prog, &mut counter, outputter) {
-                        Ok(()) => {}
-                        Err(LangError::Throw(v)) => return Err(LangError::Throw(v)),
-                        Err(LangError::CatchUnwind(_)) => {
-                            // catch unwind is trying to unwind more catches than exist
-                            panic!("catchunwind escaped outermost catch block");
-                        },
-                    }
-                }
+                Err(LangError::Throw(v)) => return Err(LangError::Throw(v)),
+                Err(LangError::CatchUnwind(_)) => {
+                    // catch unwind is trying to unwind more catches than exist
+                    panic!("catchunwind escaped outermost catch block");
+                },
             }
         }
         Ok(())
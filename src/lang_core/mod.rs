@@ -1,3 +1,9 @@
 pub mod parse;
 pub mod bytecode;
-pub mod interp;
\ No newline at end of file
+pub mod optimise;
+pub mod interp;
+pub mod convert;
+#[cfg(feature = "bytecode-files")]
+pub mod bytecode_file;
+#[cfg(feature = "serde-values")]
+pub mod serde_values;
@@ -0,0 +1,239 @@
+use std::collections::HashSet;
+
+use crate::lang_core::bytecode::Instruction;
+use crate::builtins::math::is_safe_int;
+
+fn jump_target(inst: &Instruction) -> Option<usize> {
+    match inst {
+        Instruction::IFFALSE(i) |
+        Instruction::IFNOTTYPE(_, i) |
+        Instruction::GOTO(i) |
+        Instruction::STARTCATCH(i) |
+        Instruction::FOREACHITER(i) |
+        Instruction::FORTEST(i) => Some(*i),
+        _ => None,
+    }
+}
+
+fn jump_target_mut(inst: &mut Instruction) -> Option<&mut usize> {
+    match inst {
+        Instruction::IFFALSE(i) |
+        Instruction::IFNOTTYPE(_, i) |
+        Instruction::GOTO(i) |
+        Instruction::STARTCATCH(i) |
+        Instruction::FOREACHITER(i) |
+        Instruction::FORTEST(i) => Some(i),
+        _ => None,
+    }
+}
+
+// every name that the program could assign to, anywhere
+// a builtin can only be folded if it's never shadowed by one of these
+fn bound_names(prog: &[Instruction]) -> HashSet<String> {
+    let mut names = HashSet::new();
+    for inst in prog {
+        match inst {
+            Instruction::SETVAR(name) |
+            Instruction::DELVAR(name) |
+            Instruction::SETNONLOCAL(name) |
+            Instruction::FORSTART(name) |
+            Instruction::FOREACHSTART(name) => {
+                names.insert(name.clone());
+            },
+            Instruction::PUSHSCOPE(arg_names) |
+            Instruction::CREATEFUNC(_, arg_names, _, _, _) => {
+                names.extend(arg_names.iter().cloned());
+            },
+            _ => {}
+        }
+    }
+    names
+}
+
+// the pure arithmetic builtins, with the same arg rules as in builtins::math
+fn fold_builtin(name: &str, args: &[f64]) -> Option<f64> {
+    match (name, args) {
+        ("add", [first, rest @ ..]) if !rest.is_empty() => {
+            Some(rest.iter().fold(*first, |acc, n| acc + n))
+        },
+        ("mul", [first, rest @ ..]) if !rest.is_empty() => {
+            Some(rest.iter().fold(*first, |acc, n| acc * n))
+        },
+        ("sub", [a, b]) => Some(a - b),
+        ("fdiv", [a, b]) => Some(a / b),
+        ("mod", [a, b]) => Some(a % b),
+        _ => None,
+    }
+}
+
+fn const_num(inst: &Instruction) -> Option<f64> {
+    match inst {
+        Instruction::PUSHNUM(n) |
+        Instruction::PUSHASTSTR(_, Some(n)) => Some(*n),
+        _ => None,
+    }
+}
+
+// rewrites the instructions of a single segment, and points all of its jumps
+// at where their targets ended up
+// rewrite is given the instructions output so far, along with the original index
+// of each, and returns true if it consumed the current instruction itself
+fn rewrite_segment<F>(code: &[Instruction], mut rewrite: F) -> Vec<Instruction>
+    where F: FnMut(usize, &Instruction, &mut Vec<Instruction>, &mut Vec<usize>) -> bool
+{
+    let mut out = Vec::with_capacity(code.len());
+    let mut origins = Vec::with_capacity(code.len());
+    let mut new_index = Vec::with_capacity(code.len() + 1);
+    for (i, inst) in code.iter().enumerate() {
+        new_index.push(out.len());
+        if !rewrite(i, inst, &mut out, &mut origins) {
+            out.push(inst.clone());
+            origins.push(i);
+        }
+    }
+    new_index.push(out.len());
+    for inst in out.iter_mut() {
+        if let Some(target) = jump_target_mut(inst) {
+            *target = new_index[*target];
+        }
+    }
+    out
+}
+
+fn fold_constants(code: &[Instruction], bound: &HashSet<String>) -> Vec<Instruction> {
+    let targets: HashSet<usize> = code.iter().filter_map(jump_target).collect();
+
+    rewrite_segment(code, |i, inst, out, origins| {
+        let (argc, direct_output) = match inst {
+            Instruction::CALLFUNC(argc, direct_output) => (*argc, *direct_output),
+            _ => return false,
+        };
+        if out.len() < argc + 1 || targets.contains(&i) {
+            return false;
+        }
+        let start = out.len() - argc - 1;
+        let name = match &out[start] {
+            Instruction::GETVAR(name) if !bound.contains(name) => name,
+            _ => return false,
+        };
+        // the call can only be merged into one instruction if nothing jumps
+        // into the middle of it
+        if origins[start+1..].iter().any(|o| targets.contains(o)) {
+            return false;
+        }
+        let args: Option<Vec<f64>> = out[start+1..].iter().map(const_num).collect();
+        let val = match args.and_then(|args| fold_builtin(name, &args)) {
+            // an inexact integer result is left for --int-checks to catch at run time
+            Some(val) if val.fract() != 0.0 || is_safe_int(val) => val,
+            _ => return false,
+        };
+
+        let origin = origins[start];
+        out.truncate(start);
+        origins.truncate(start);
+        out.push(Instruction::PUSHNUM(val));
+        origins.push(origin);
+        if direct_output {
+            out.push(Instruction::OUTPUTVAL);
+            origins.push(i);
+        }
+        true
+    })
+}
+
+// jumps that land on a GOTO are sent straight to where that GOTO goes
+fn thread_jumps(code: &[Instruction]) -> Vec<Instruction> {
+    let mut code = code.to_vec();
+    for i in 0..code.len() {
+        let mut target = match jump_target(&code[i]) {
+            Some(target) => target,
+            None => continue,
+        };
+        // a cycle of GOTOs can't be followed to the end, so give up
+        // once there have been more steps than there are instructions
+        let mut steps = 0;
+        while let Some(Instruction::GOTO(next)) = code.get(target) {
+            if steps == code.len() {
+                break;
+            }
+            target = *next;
+            steps += 1;
+        }
+        if let Some(t) = jump_target_mut(&mut code[i]) {
+            *t = target;
+        }
+    }
+    code
+}
+
+// marks every instruction that running the segment from its start could get to
+fn reachable(code: &[Instruction]) -> Vec<bool> {
+    let mut seen = vec![false; code.len()];
+    let mut todo = vec![0];
+    while let Some(i) = todo.pop() {
+        if i >= code.len() || seen[i] {
+            continue;
+        }
+        seen[i] = true;
+        if let Some(target) = jump_target(&code[i]) {
+            todo.push(target);
+        }
+        match &code[i] {
+            Instruction::GOTO(_) | Instruction::THROWVAL | Instruction::END => {},
+            _ => {
+                todo.push(i + 1);
+            }
+        }
+    }
+    seen
+}
+
+fn remove_dead_code(code: &[Instruction]) -> Vec<Instruction> {
+    let live = reachable(code);
+    // END stays even when it can't be reached, so the segment is always terminated
+    rewrite_segment(code, |i, inst, _, _| {
+        !live[i] && !matches!(inst, Instruction::END)
+    })
+}
+
+fn optimise_segment(prog: &[Instruction], bound: &HashSet<String>, fold: bool) -> Vec<Instruction> {
+    // function bodies are linked in after the END of the segment that creates them
+    let code_len = prog.iter()
+        .position(|inst| matches!(inst, Instruction::END))
+        .map_or(prog.len(), |i| i + 1);
+    let code = match fold {
+        true => fold_constants(&prog[..code_len], bound),
+        false => prog[..code_len].to_vec(),
+    };
+    let code = thread_jumps(&code);
+    let mut code = remove_dead_code(&code);
+
+    let mut funcs = Vec::new();
+    for (i, inst) in code.iter().enumerate() {
+        if let Instruction::CREATEFUNC(_, _, _, loc, size) = inst {
+            funcs.push((i, optimise_segment(&prog[*loc..*loc + *size], bound, fold)));
+        }
+    }
+    for (i, body) in funcs {
+        let current_len = code.len();
+        match &mut code[i] {
+            Instruction::CREATEFUNC(_, _, _, loc, size) => {
+                *loc = current_len;
+                *size = body.len();
+            },
+            _ => unreachable!(),
+        }
+        code.extend(body);
+    }
+    code
+}
+
+pub fn optimise(prog: Vec<Instruction>, predefined: &[String]) -> Vec<Instruction> {
+    let mut bound = bound_names(&prog);
+    bound.extend(predefined.iter().cloned());
+    // an imported file can bind any name at all, so nothing can be folded
+    let fold = !prog.iter().any(|inst| {
+        matches!(inst, Instruction::GETVAR(name) if name == "import")
+    });
+    optimise_segment(&prog, &bound, fold)
+}
@@ -1,7 +1,7 @@
 extern crate nom;
 use nom::{
     IResult, Err, InputTake, FindSubstring, InputLength,
-    error::ParseError,
+    error::{ParseError, ErrorKind},
     multi::{many0, many1, fold_many0, separated_list},
     bytes::complete::{tag, take_until, take_till1},
     combinator::{not, map, opt},
@@ -18,20 +18,31 @@ pub enum AST {
     String(String, Option<f64>),
     Variable(VarAccess),
     SetVar(VarAccess, Vec<AST>),
+    SetOp(VarAccess, Vec<Vec<AST>>),
     DelVar(VarAccess),
+    // {set:[a:b:c]:{l};}, which sets each name to the item of l in its place
+    SetUnpack(Vec<String>, Vec<AST>),
+    // {set:{a:b}:{m};}, which sets each name to what m has under that key
+    SetUnpackMap(Vec<String>, Vec<AST>),
 }
 
 #[derive(Clone, Debug)]
 pub enum Accessor {
     Index(Vec<AST>),
+    Slice(Vec<Vec<AST>>),
     Attr(Vec<AST>),
-    Call(Vec<Vec<AST>>)
+    Call(Vec<Vec<AST>>),
+    // a call with at least one arg written as *{list}, which has the items
+    // of the list passed in its place, each marked true
+    SpreadCall(Vec<(bool, Vec<AST>)>)
 }
 
 #[derive(Clone, Debug)]
 pub struct VarAccess {
     pub(crate) value: Vec<AST>,
     pub(crate) accessors: Vec<Accessor>,
+    // the source line it starts on, for syntax errors
+    pub(crate) line: usize,
 }
 
 enum ASTVariants {
@@ -94,7 +105,7 @@ fn take_until_or_eof<T, Input, Error: ParseError<Input>>(
     }
 }
 
-fn remove_comments(input: &str) -> Result<String, ()> {
+fn remove_comments(input: &str) -> Result<(String, OffsetMap), ()> {
     let (rem, strings) = delimited(
         opt(parse_comment),
         separated_list(
@@ -106,12 +117,33 @@ fn remove_comments(input: &str) -> Result<String, ()> {
     if rem.len() > 0 {
         return Err(());
     }
-    let size = strings.iter().map(|s| s.len()).sum();
+    Ok(join_pieces(input, strings))
+}
+
+// where each piece of some preprocessed source was taken from, as pairs of
+// its offset in the output and its offset in the input
+type OffsetMap = Vec<(usize, usize)>;
+
+// joins pieces sliced out of input, keeping track of where they came from
+fn join_pieces(input: &str, pieces: Vec<&str>) -> (String, OffsetMap) {
+    let size = pieces.iter().map(|s| s.len()).sum();
     let mut ret = String::with_capacity(size);
-    for s in strings {
+    let mut map = Vec::with_capacity(pieces.len());
+    for s in pieces {
+        map.push((ret.len(), s.as_ptr() as usize - input.as_ptr() as usize));
         ret.push_str(s);
     }
-    Ok(ret)
+    (ret, map)
+}
+
+fn map_offset(map: &[(usize, usize)], offset: usize) -> usize {
+    // the last piece starting at or before the offset, skipping over any empty ones
+    let i = map.partition_point(|&(out, _)| out <= offset);
+    if i == 0 {
+        return offset;
+    }
+    let (out, orig) = map[i - 1];
+    orig + (offset - out)
 }
 
 fn parse_escaped_char(chars: &[char]) -> impl Fn(&str) -> IResult<&str, Cow<str>> + '_ {
@@ -184,7 +216,10 @@ macro_rules! match_strings {
     };
 }
 
+// the parser only ever sees what's left of the source, so lines start out as
+// how far from the end each access was, and are fixed up by set_lines
 fn parse_var_access(input: &str) -> IResult<&str, VarAccess> {
+    let line = input.len();
     // first thing in a var is either a string (identifier), or another expression
     // allows for {a.b} or {{a}.b}
     // {a} and {{a}} are equivalent
@@ -197,6 +232,18 @@ fn parse_var_access(input: &str) -> IResult<&str, VarAccess> {
             |v| Accessor::Index(v)
         )(input)
     }
+    fn parse_slice(input: &str) -> IResult<&str, Accessor> {
+        // look for a[{b}:{c}] or a[{b}:{c}:{d}], any of which can be empty
+        let (input, _) = tag("[")(input)?;
+        let (input, start) = parse_block_arg(&['{', ':', ']'])(input)?;
+        let (input, _) = char(':')(input)?;
+        let (input, end) = parse_block_arg(&['{', ':', ']'])(input)?;
+        let (input, step) = opt(preceded(char(':'), parse_block_arg(&['{', ':', ']'])))(input)?;
+        let (input, _) = tag("]")(input)?;
+        let mut parts = vec![start, end];
+        parts.extend(step);
+        Ok((input, Accessor::Slice(parts)))
+    }
     fn parse_attr(input: &str) -> IResult<&str, Accessor> {
         // look for a.{b}, retrieve the {b}
         map(
@@ -208,18 +255,34 @@ fn parse_var_access(input: &str) -> IResult<&str, VarAccess> {
         // look for a:b:c:d;, retrieve the [b, c, d]
         // a; would retrieve []
         let mut args = Vec::new();
+        let mut spread = Vec::new();
         loop {
             let (i, sep) = match_strings!(":", ";")(input)?;
             match sep {
                 ":" => {
-                    // new arg found
-                    let (i, arg) = parse_block_arg(&['{', ':', ';'])(i)?;
-                    args.push(arg);
-                    input = i;
+                    // new arg found, spread only if it's nothing but a * and a block
+                    let spread_arg: IResult<&str, AST> = preceded(char('*'), parse_block)(i);
+                    match spread_arg {
+                        Ok((rest, ast)) if rest.starts_with(&[':', ';'][..]) => {
+                            args.push(vec![ast]);
+                            spread.push(true);
+                            input = rest;
+                        },
+                        _ => {
+                            let (i, arg) = parse_block_arg(&['{', ':', ';'])(i)?;
+                            args.push(arg);
+                            spread.push(false);
+                            input = i;
+                        },
+                    }
                     continue;
                 },
                 ";" => {
                     // end of function call
+                    if spread.contains(&true) {
+                        let args = spread.into_iter().zip(args).collect();
+                        return Ok((i, Accessor::SpreadCall(args)));
+                    }
                     return Ok((i, Accessor::Call(args)));
                 },
                 _ => unreachable!()
@@ -228,15 +291,25 @@ fn parse_var_access(input: &str) -> IResult<&str, VarAccess> {
     }
 
     // var accesses are some list of these types
-    let (input, accessors) = many0(
+    let (input, mut accessors) = many0(
         alt((
+            parse_slice,
             parse_index,
             parse_attr,
             parse_call
         ))
     )(input)?;
 
-    Ok((input, VarAccess {value, accessors}))
+    // the args of a special form aren't values to spread, so {if:1:*{x};}
+    // still outputs a * in front of x
+    if let ([AST::String(name, _)], [first, ..]) = (&value[..], &mut accessors[..]) {
+        if SPECIAL_FORMS.contains(&&name[..]) {
+            let accessor = std::mem::replace(first, Accessor::Call(Vec::new()));
+            *first = unspread(Some(accessor)).unwrap();
+        }
+    }
+
+    Ok((input, VarAccess {value, accessors, line}))
 }
 
 fn parse_block_arg(chars: &[char]) -> impl Fn(&str) -> IResult<&str, Vec<AST>> + '_ {
@@ -249,6 +322,7 @@ fn parse_block_arg(chars: &[char]) -> impl Fn(&str) -> IResult<&str, Vec<AST>> +
                 }),
                 map(parse_escaped_block, ASTVariants::ASTVec),
                 map(parse_set_block, ASTVariants::ASTValue),
+                map(parse_setop_block, ASTVariants::ASTValue),
                 map(parse_func_block, ASTVariants::ASTValue),
                 map(parse_del_block, ASTVariants::ASTValue),
                 map(parse_block, ASTVariants::ASTValue)
@@ -259,11 +333,49 @@ fn parse_block_arg(chars: &[char]) -> impl Fn(&str) -> IResult<&str, Vec<AST>> +
     }
 }
 
+// every name that ast_bytecode compiles as a form of its own when called,
+// rather than as a call to a variable
+const SPECIAL_FORMS: &[&str] = &[
+    "if", "matchtype", "lambda", "list", "map", "nonlocal", "swap", "throw",
+    "catch", "catchkind", "transform", "with", "void", "let", "do", "discard",
+    "while", "voidwhile", "for", "foreach", "letfor", "letforeach", "listcomp",
+    "continue", "break",
+];
+
+// set, setop and del only take plain args, so a * before a block in one is
+// put back as it was written, as text in front of the block
+fn unspread(accessor: Option<Accessor>) -> Option<Accessor> {
+    match accessor {
+        Some(Accessor::SpreadCall(args)) => {
+            let args = args.into_iter()
+                .map(|(spread, mut arg)| {
+                    if spread {
+                        arg.insert(0, AST::String(String::from("*"), None));
+                    }
+                    arg
+                })
+                .collect();
+            Some(Accessor::Call(args))
+        },
+        accessor => accessor,
+    }
+}
+
 fn parse_set_block(input: &str) -> IResult<&str, AST> {
     let (input, _) = tag("{set:")(input)?;
+    if let (input, Some(names)) = opt(parse_unpack_names('[', ']'))(input)? {
+        let (input, val) = parse_block_arg(&['{', ':', ';'])(input)?;
+        let (input, _) = tag(";}")(input)?;
+        return Ok((input, AST::SetUnpack(names, val)));
+    }
+    if let (input, Some(names)) = opt(parse_unpack_names('{', '}'))(input)? {
+        let (input, val) = parse_block_arg(&['{', ':', ';'])(input)?;
+        let (input, _) = tag(";}")(input)?;
+        return Ok((input, AST::SetUnpackMap(names, val)));
+    }
     let (input, mut access) = parse_var_access(input)?;
     let val;
-    match access.accessors.pop() {
+    match unspread(access.accessors.pop()) {
         Some(Accessor::Call(mut args)) => {
             assert!(args.len() == 1);
             val = args.pop().unwrap();
@@ -276,10 +388,43 @@ fn parse_set_block(input: &str) -> IResult<&str, AST> {
     Ok((input, AST::SetVar(access, val)))
 }
 
+// the [a:b:c] or {a:b:c} that a destructuring set takes in place of a
+// single name, up to the : before the value
+// {set:{x}[a]:1;} still sets into the var named by {x}, as the } has to
+// be followed by the : for it to be taken as names
+fn parse_unpack_names(open: char, close: char) -> impl Fn(&str) -> IResult<&str, Vec<String>> {
+    move |input: &str| {
+        let (input, names) = delimited(
+            char(open),
+            separated_list(char(':'), parse_string(&[':', ';', '{', '}', '[', ']', '.'])),
+            pair(char(close), char(':'))
+        )(input)?;
+        if names.is_empty() {
+            return Err(Err::Error((input, ErrorKind::Verify)));
+        }
+        Ok((input, names))
+    }
+}
+
+// {setop:x:add:1;} is {set:x:{add:{x}:1;};}, except that any container
+// and index in the target are only evaluated once
+fn parse_setop_block(input: &str) -> IResult<&str, AST> {
+    let (input, _) = tag("{setop:")(input)?;
+    let (input, mut access) = parse_var_access(input)?;
+    let args = match unspread(access.accessors.pop()) {
+        Some(Accessor::Call(args)) => args,
+        _ => {
+            panic!("invalid call to setop");
+        },
+    };
+    let (input, _) = tag("}")(input)?;
+    Ok((input, AST::SetOp(access, args)))
+}
+
 fn parse_del_block(input: &str) -> IResult<&str, AST> {
     let (input, _) = tag("{del:")(input)?;
     let (input, mut access) = parse_var_access(input)?;
-    match access.accessors.pop() {
+    match unspread(access.accessors.pop()) {
         Some(Accessor::Call(args)) => {
             assert!(args.is_empty());
         },
@@ -292,6 +437,7 @@ fn parse_del_block(input: &str) -> IResult<&str, AST> {
 }
 
 fn parse_func_block(input: &str) -> IResult<&str, AST> {
+    let line = input.len();
     let (input, _) = tag("{func:{")(input)?;
     not(tag(">"))(input)?;
     let (input, name) = parse_string(&[':', ';', '{', '}', '[', ']', '.'])(input)?;
@@ -333,12 +479,14 @@ fn parse_func_block(input: &str) -> IResult<&str, AST> {
     Ok((input, AST::SetVar(
         VarAccess {
             value: vec![AST::String(name, name_v)],
-            accessors: Vec::new()
+            accessors: Vec::new(),
+            line,
         },
         vec![AST::Variable(
             VarAccess {
                 value: vec![AST::String(String::from("lambda"), None)],
-                accessors: vec![Accessor::Call(args)]
+                accessors: vec![Accessor::Call(args)],
+                line,
             }
         )]
     )))
@@ -349,7 +497,31 @@ fn parse_block(input: &str) -> IResult<&str, AST> {
     not(char('!'))(input)?;
     not(char('>'))(input)?;
 
-    let (input, var) = parse_var_access(input)?;
+    let (mut input, mut var) = parse_var_access(input)?;
+
+    // {a:b;|f:c;|g;} pipes each result into the next call as its first arg,
+    // so it is the same as writing {g:{f:{a:b;}:c;};}
+    // the | can have whitespace around it, so long chains can be split over lines
+    while let Some(Accessor::Call(_) | Accessor::SpreadCall(_)) = var.accessors.last() {
+        let (i, pipe) = opt(delimited(multispace0, char('|'), multispace0))(input)?;
+        if pipe.is_none() {
+            break;
+        }
+        let (i, mut stage) = parse_var_access(i)?;
+        match stage.accessors.last_mut() {
+            Some(Accessor::Call(args)) => {
+                args.insert(0, vec![AST::Variable(var)]);
+            },
+            Some(Accessor::SpreadCall(args)) => {
+                args.insert(0, (false, vec![AST::Variable(var)]));
+            },
+            _ => {
+                return Err(Err::Error((i, ErrorKind::Verify)));
+            }
+        }
+        input = i;
+        var = stage;
+    }
 
     let (input, _) = char('}')(input)?;
 
@@ -366,6 +538,7 @@ fn parse_escaped_block(input: &str) -> IResult<&str, Vec<AST>> {
             }),
             map(parse_escaped_block, ASTVariants::ASTVec),
             map(parse_set_block, ASTVariants::ASTValue),
+            map(parse_setop_block, ASTVariants::ASTValue),
             map(parse_func_block, ASTVariants::ASTValue),
             map(parse_del_block, ASTVariants::ASTValue),
             map(parse_block, ASTVariants::ASTValue)
@@ -395,6 +568,7 @@ fn parse_base(input: &str) -> IResult<&str, Vec<AST>> {
             }),
             map(parse_escaped_block, ASTVariants::ASTVec),
             map(parse_set_block, ASTVariants::ASTValue),
+            map(parse_setop_block, ASTVariants::ASTValue),
             map(parse_func_block, ASTVariants::ASTValue),
             map(parse_del_block, ASTVariants::ASTValue),
             map(parse_block, ASTVariants::ASTValue)
@@ -404,13 +578,13 @@ fn parse_base(input: &str) -> IResult<&str, Vec<AST>> {
     )(input)
 }
 
-fn parse_oneline(input: String) -> Result<String, ()> {
+fn parse_oneline(input: &str) -> Result<(String, OffsetMap), ()> {
     fn check_oneline(inp: &str) -> IResult<&str, &str> {
         let (input, _) = multispace0(inp)?;
         tag("{#>oneline}")(input)
     }
 
-    if let Ok((input, _)) = check_oneline(&input) {
+    if let Ok((rest, _)) = check_oneline(input) {
         let (rem, strings) = delimited(
             multispace0,
             separated_list(
@@ -418,27 +592,77 @@ fn parse_oneline(input: String) -> Result<String, ()> {
                 take_till1(|c| c == '\r' || c == '\n')
             ),
             multispace0
-        )(input).map_err(|_: Err<()>| ())?;
+        )(rest).map_err(|_: Err<()>| ())?;
         if rem.len() > 0 {
             return Err(());
         }
-        let size = strings.iter().map(|s| s.len()).sum();
-        let mut ret = String::with_capacity(size);
-        for s in strings {
-            ret.push_str(s);
-        }
-        Ok(ret)
+        Ok(join_pieces(input, strings))
     } else {
-        Ok(input)
+        Ok((input.to_owned(), vec![(0, 0)]))
+    }
+}
+
+fn set_access_lines(var: &mut VarAccess, line_of: &dyn Fn(usize) -> usize) {
+    var.line = line_of(var.line);
+    set_lines(&mut var.value, line_of);
+    for accessor in &mut var.accessors {
+        match accessor {
+            Accessor::Index(arg) |
+            Accessor::Attr(arg) => set_lines(arg, line_of),
+            Accessor::Slice(args) |
+            Accessor::Call(args) => {
+                for arg in args {
+                    set_lines(arg, line_of);
+                }
+            },
+            Accessor::SpreadCall(args) => {
+                for (_, arg) in args {
+                    set_lines(arg, line_of);
+                }
+            },
+        }
+    }
+}
+
+fn set_lines(asts: &mut [AST], line_of: &dyn Fn(usize) -> usize) {
+    for ast in asts {
+        match ast {
+            AST::String(_, _) => {},
+            AST::Variable(var) |
+            AST::DelVar(var) => set_access_lines(var, line_of),
+            AST::SetVar(var, val) => {
+                set_access_lines(var, line_of);
+                set_lines(val, line_of);
+            },
+            AST::SetOp(var, args) => {
+                set_access_lines(var, line_of);
+                for arg in args {
+                    set_lines(arg, line_of);
+                }
+            },
+            AST::SetUnpack(_, val) |
+            AST::SetUnpackMap(_, val) => set_lines(val, line_of),
+        }
     }
 }
 
 pub fn run_parser(input: &str) -> Result<Vec<AST>, ()> {
-    let input = remove_comments(input)?;
-    let input = parse_oneline(input)?;
-    match parse_base(&input) {
-        Ok((rem, ast)) => {
+    let (stripped, comment_map) = remove_comments(input)?;
+    let (code, oneline_map) = parse_oneline(&stripped)?;
+    match parse_base(&code) {
+        Ok((rem, mut ast)) => {
             if rem.len() == 0 {
+                // each access's distance from the end is traced back through
+                // both rounds of preprocessing to where it was in the source
+                let line_starts: Vec<usize> = input.match_indices('\n').map(|(i, _)| i).collect();
+                let line_of = |from_end: usize| {
+                    let offset = map_offset(&oneline_map, code.len() - from_end);
+                    let offset = map_offset(&comment_map, offset);
+                    match line_starts.binary_search(&offset) {
+                        Ok(i) | Err(i) => i + 1,
+                    }
+                };
+                set_lines(&mut ast, &line_of);
                 Ok(ast)
             } else {
                 Err(())
@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use serde::ser::{self, Serialize};
+use crate::lang_core::interp::{VarValues, Gc, new_value, borrow_val};
+
+// turns anything serde can serialize into a value, for passing structs in
+// through Context::bind without writing IntoVarValues for them by hand
+// structs and maps become maps, sequences and tuples become lists, and an
+// enum variant holding data becomes a map from its name to that data
+pub fn serialize_varvalues<T: Serialize + ?Sized>(val: &T) -> Result<Gc<VarValues>, SerializeError> {
+    val.serialize(ValueSerializer)
+}
+
+#[derive(Debug)]
+pub struct SerializeError(String);
+
+impl fmt::Display for SerializeError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{}", self.0)
+    }
+}
+
+impl std::error::Error for SerializeError {}
+
+impl ser::Error for SerializeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        SerializeError(msg.to_string())
+    }
+}
+
+type SerializeResult = Result<Gc<VarValues>, SerializeError>;
+
+fn str_value(s: &str) -> Gc<VarValues> {
+    new_value(VarValues::Str(s.to_owned()))
+}
+
+// wraps the data of an enum variant, as {variant: data}
+fn variant_value(variant: &'static str, val: Gc<VarValues>) -> Gc<VarValues> {
+    let mut map = HashMap::with_capacity(1);
+    map.insert(variant.to_owned(), val);
+    new_value(VarValues::Map(Rc::new(map)))
+}
+
+struct ValueSerializer;
+
+impl ser::Serializer for ValueSerializer {
+    type Ok = Gc<VarValues>;
+    type Error = SerializeError;
+    type SerializeSeq = SeqSerializer;
+    type SerializeTuple = SeqSerializer;
+    type SerializeTupleStruct = SeqSerializer;
+    type SerializeTupleVariant = SeqSerializer;
+    type SerializeMap = MapSerializer;
+    type SerializeStruct = MapSerializer;
+    type SerializeStructVariant = MapSerializer;
+
+    fn serialize_bool(self, v: bool) -> SerializeResult {
+        Ok(new_value(VarValues::Num(if v {1.0} else {0.0})))
+    }
+    // numbers are all floats in the end, the same as with IntoVarValues
+    fn serialize_i8(self, v: i8) -> SerializeResult {
+        self.serialize_f64(v as f64)
+    }
+    fn serialize_i16(self, v: i16) -> SerializeResult {
+        self.serialize_f64(v as f64)
+    }
+    fn serialize_i32(self, v: i32) -> SerializeResult {
+        self.serialize_f64(v as f64)
+    }
+    fn serialize_i64(self, v: i64) -> SerializeResult {
+        self.serialize_f64(v as f64)
+    }
+    fn serialize_u8(self, v: u8) -> SerializeResult {
+        self.serialize_f64(v as f64)
+    }
+    fn serialize_u16(self, v: u16) -> SerializeResult {
+        self.serialize_f64(v as f64)
+    }
+    fn serialize_u32(self, v: u32) -> SerializeResult {
+        self.serialize_f64(v as f64)
+    }
+    fn serialize_u64(self, v: u64) -> SerializeResult {
+        self.serialize_f64(v as f64)
+    }
+    fn serialize_f32(self, v: f32) -> SerializeResult {
+        self.serialize_f64(v as f64)
+    }
+    fn serialize_f64(self, v: f64) -> SerializeResult {
+        Ok(new_value(VarValues::Num(v)))
+    }
+    fn serialize_char(self, v: char) -> SerializeResult {
+        Ok(new_value(VarValues::Str(v.to_string())))
+    }
+    fn serialize_str(self, v: &str) -> SerializeResult {
+        Ok(str_value(v))
+    }
+    // bytes become a list of numbers, like the bytes builtin gives
+    fn serialize_bytes(self, v: &[u8]) -> SerializeResult {
+        let vals = v.iter().map(|b| new_value(VarValues::Num(*b as f64))).collect();
+        Ok(new_value(VarValues::List(Rc::new(vals))))
+    }
+    fn serialize_none(self) -> SerializeResult {
+        Ok(new_value(VarValues::Nil))
+    }
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> SerializeResult {
+        value.serialize(self)
+    }
+    fn serialize_unit(self) -> SerializeResult {
+        Ok(new_value(VarValues::Nil))
+    }
+    fn serialize_unit_struct(self, _name: &'static str) -> SerializeResult {
+        Ok(new_value(VarValues::Nil))
+    }
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, variant: &'static str) -> SerializeResult {
+        Ok(str_value(variant))
+    }
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> SerializeResult {
+        value.serialize(self)
+    }
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T
+    ) -> SerializeResult {
+        Ok(variant_value(variant, value.serialize(self)?))
+    }
+    fn serialize_seq(self, len: Option<usize>) -> Result<SeqSerializer, SerializeError> {
+        Ok(SeqSerializer {
+            variant: None,
+            vals: Vec::with_capacity(len.unwrap_or(0)),
+        })
+    }
+    fn serialize_tuple(self, len: usize) -> Result<SeqSerializer, SerializeError> {
+        self.serialize_seq(Some(len))
+    }
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<SeqSerializer, SerializeError> {
+        self.serialize_seq(Some(len))
+    }
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize
+    ) -> Result<SeqSerializer, SerializeError> {
+        Ok(SeqSerializer {
+            variant: Some(variant),
+            vals: Vec::with_capacity(len),
+        })
+    }
+    fn serialize_map(self, len: Option<usize>) -> Result<MapSerializer, SerializeError> {
+        Ok(MapSerializer {
+            variant: None,
+            vals: HashMap::with_capacity(len.unwrap_or(0)),
+            key: None,
+        })
+    }
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<MapSerializer, SerializeError> {
+        self.serialize_map(Some(len))
+    }
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize
+    ) -> Result<MapSerializer, SerializeError> {
+        Ok(MapSerializer {
+            variant: Some(variant),
+            vals: HashMap::with_capacity(len),
+            key: None,
+        })
+    }
+}
+
+struct SeqSerializer {
+    variant: Option<&'static str>,
+    vals: Vec<Gc<VarValues>>,
+}
+
+impl SeqSerializer {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerializeError> {
+        self.vals.push(value.serialize(ValueSerializer)?);
+        Ok(())
+    }
+    fn finish(self) -> SerializeResult {
+        let list = new_value(VarValues::List(Rc::new(self.vals)));
+        match self.variant {
+            Some(variant) => Ok(variant_value(variant, list)),
+            None => Ok(list),
+        }
+    }
+}
+
+impl ser::SerializeSeq for SeqSerializer {
+    type Ok = Gc<VarValues>;
+    type Error = SerializeError;
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerializeError> {
+        self.push(value)
+    }
+    fn end(self) -> SerializeResult {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for SeqSerializer {
+    type Ok = Gc<VarValues>;
+    type Error = SerializeError;
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerializeError> {
+        self.push(value)
+    }
+    fn end(self) -> SerializeResult {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleStruct for SeqSerializer {
+    type Ok = Gc<VarValues>;
+    type Error = SerializeError;
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerializeError> {
+        self.push(value)
+    }
+    fn end(self) -> SerializeResult {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleVariant for SeqSerializer {
+    type Ok = Gc<VarValues>;
+    type Error = SerializeError;
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerializeError> {
+        self.push(value)
+    }
+    fn end(self) -> SerializeResult {
+        self.finish()
+    }
+}
+
+struct MapSerializer {
+    variant: Option<&'static str>,
+    vals: HashMap<String, Gc<VarValues>>,
+    // the key given by serialize_key, waiting for its value
+    key: Option<String>,
+}
+
+impl MapSerializer {
+    fn finish(self) -> SerializeResult {
+        let map = new_value(VarValues::Map(Rc::new(self.vals)));
+        match self.variant {
+            Some(variant) => Ok(variant_value(variant, map)),
+            None => Ok(map),
+        }
+    }
+}
+
+// map keys are always strings, so only keys that print as one can be used
+fn key_string<T: Serialize + ?Sized>(key: &T) -> Result<String, SerializeError> {
+    let val = key.serialize(ValueSerializer)?;
+    let val = borrow_val(&val).map_err(|_| SerializeError(String::from("value is in use")))?;
+    match &*val {
+        v @ VarValues::Str(_) |
+        v @ VarValues::Num(_) => Ok(v.to_string()),
+        _ => Err(SerializeError(String::from("map keys must be strings or numbers"))),
+    }
+}
+
+impl ser::SerializeMap for MapSerializer {
+    type Ok = Gc<VarValues>;
+    type Error = SerializeError;
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), SerializeError> {
+        self.key = Some(key_string(key)?);
+        Ok(())
+    }
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerializeError> {
+        let key = match self.key.take() {
+            Some(key) => key,
+            None => return Err(SerializeError(String::from("map value given without a key"))),
+        };
+        self.vals.insert(key, value.serialize(ValueSerializer)?);
+        Ok(())
+    }
+    fn end(self) -> SerializeResult {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStruct for MapSerializer {
+    type Ok = Gc<VarValues>;
+    type Error = SerializeError;
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), SerializeError> {
+        self.vals.insert(key.to_owned(), value.serialize(ValueSerializer)?);
+        Ok(())
+    }
+    fn end(self) -> SerializeResult {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for MapSerializer {
+    type Ok = Gc<VarValues>;
+    type Error = SerializeError;
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), SerializeError> {
+        self.vals.insert(key.to_owned(), value.serialize(ValueSerializer)?);
+        Ok(())
+    }
+    fn end(self) -> SerializeResult {
+        self.finish()
+    }
+}
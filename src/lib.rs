@@ -0,0 +1,7 @@
+// the interpreter as a library, so other programs can embed it, register
+// builtins of their own and pass values in and out
+// a program using it also needs libgc's GcAllocator as its global allocator
+pub mod lang_core;
+pub mod builtins;
+
+use lang_core::{bytecode, interp};
@@ -1,16 +1,18 @@
-mod lang_core;
-mod builtins;
-
-use lang_core::{parse, bytecode, interp::{self, LangError, StdOutOutput}};
+use individual_project::{lang_core, builtins};
+use lang_core::{parse, bytecode::{self, Instruction}, interp::{self, LangError, StdOutOutput, EscapingOutput, EscapePolicy}};
+use lang_core::convert::{IntoVarValues, to_varvalues};
+#[cfg(feature = "bytecode-files")]
+use lang_core::bytecode_file;
 use libgc::{GcAllocator};
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches};
 use std::fs;
+use std::collections::BTreeMap;
 
 #[global_allocator]
 static ALLOCATOR: GcAllocator = GcAllocator;
 
 fn main() {
-    let matches = App::new("project")
+    let app = App::new("project")
         .help("Individual Project\n\
                Language Interpreter v1.0\n\
                Z. Nuccio (k1891842@kcl.ac.uk)\n\
@@ -18,69 +20,370 @@ fn main() {
                USAGE: project <-c CODE | FILE> [args...]\
                \n\
                Options:\n\
-               -h, --help    Prints this message\n\
-               -c, --code    Interpret argument as program")
+               -h, --help        Prints this message\n\
+               -c, --code        Interpret argument as program\n\
+               -b, --bytecode    Print the compiled bytecode instead of running it\n\
+               -D, --define      Set a global variable before running, as name=value\n\
+               -l, --load        Run a file before the program, sharing its globals\n\
+               -f, --allow-fs    Let the program access files, such as through import\n\
+               -s, --strict-args Throw when a function that doesn't use args is given too many\n\
+               -i, --int-checks  Throw when adding, subtracting or multiplying integers gives\n\
+               \x20                 a result too big for a float to hold exactly\n\
+               -t, --trace       Log every function call and what it returns to stderr\n\
+               -r, --deterministic\n\
+               \x20                 Give the same output on every run, with map keys\n\
+               \x20                 in order and a fixed seed for random builtins\n\
+               -m, --max-steps N Throw once the program has run N instructions,\n\
+               \x20                 and again after every N more\n\
+               -T, --timeout SECS\n\
+               \x20                 Cancel the program once it's run for SECS seconds\n\
+               -e, --escape POLICY\n\
+               \x20                 Escape every value output for html, shell or json,\n\
+               \x20                 apart from those made with raw\n\
+               -d, --debug       Step through the program an instruction at a time,\n\
+               \x20                 reading step, continue or stack commands from stdin\n\
+               --test            Once the program has run, call every function with a name\n\
+               \x20                 starting with test_, and report which ones threw\n\
+               --compile FILE    Save the compiled program to FILE instead of running it\n\
+               --run-compiled FILE\n\
+               \x20                 Run a program saved by --compile, with no source file")
         .arg(Arg::with_name("code")
             .short("c")
             .long("code")
             .takes_value(true))
+        .arg(Arg::with_name("bytecode")
+            .short("b")
+            .long("bytecode"))
+        .arg(Arg::with_name("define")
+            .short("D")
+            .long("define")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1))
+        .arg(Arg::with_name("load")
+            .short("l")
+            .long("load")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1))
+        .arg(Arg::with_name("allow-fs")
+            .short("f")
+            .long("allow-fs"))
+        .arg(Arg::with_name("strict-args")
+            .short("s")
+            .long("strict-args"))
+        .arg(Arg::with_name("int-checks")
+            .short("i")
+            .long("int-checks"))
+        .arg(Arg::with_name("trace")
+            .short("t")
+            .long("trace"))
+        .arg(Arg::with_name("deterministic")
+            .short("r")
+            .long("deterministic"))
+        .arg(Arg::with_name("max-steps")
+            .short("m")
+            .long("max-steps")
+            .takes_value(true))
+        .arg(Arg::with_name("timeout")
+            .short("T")
+            .long("timeout")
+            .takes_value(true))
+        .arg(Arg::with_name("escape")
+            .short("e")
+            .long("escape")
+            .takes_value(true))
+        .arg(Arg::with_name("debug")
+            .short("d")
+            .long("debug"))
+        .arg(Arg::with_name("test")
+            .long("test"))
         .arg(Arg::with_name("args")
             .multiple(true)
-            .min_values(0))
-        .get_matches();
+            .min_values(0));
+    #[cfg(feature = "bytecode-files")]
+    let app = app
+        .arg(Arg::with_name("compile")
+            .long("compile")
+            .takes_value(true))
+        .arg(Arg::with_name("run-compiled")
+            .long("run-compiled")
+            .takes_value(true)
+            .conflicts_with_all(&["code", "load", "compile"]));
+    let matches = app.get_matches();
 
     let mut args = match matches.values_of("args") {
         Some(iter) => iter.collect(),
         None => Vec::new(),
     };
-    let input;
 
+    let mut defines = BTreeMap::new();
+    for define in matches.values_of("define").into_iter().flatten() {
+        match define.find('=') {
+            Some(i) => {
+                let (name, value) = (&define[..i], &define[i+1..]);
+                if add_define(&mut defines, name, value).is_err() {
+                    eprintln!("ERROR: define '{}' conflicts with another define", name);
+                    std::process::exit(1);
+                }
+            }
+            None => {
+                eprintln!("ERROR: invalid define '{}', expected name=value", define);
+                std::process::exit(1);
+            }
+        }
+    }
+    let defined_names: Vec<String> = defines.keys().cloned().collect();
+
+    // a program that was compiled ahead of time takes every arg as its own
+    let program = match load_compiled(&matches) {
+        Some(program) => program,
+        None => {
+            let input = read_input(&matches, &mut args);
+            compile_program(&matches, &input, &defined_names)
+        }
+    };
+    let args = args.into_iter().map(|s| s.to_owned()).collect();
+    #[cfg(feature = "bytecode-files")]
+    {
+        if let Some(filename) = matches.value_of("compile") {
+            if let Err(err) = bytecode_file::save_program(&program, filename) {
+                eprintln!("ERROR: could not save bytecode to '{}': {}", filename, err);
+                std::process::exit(1);
+            }
+            return;
+        }
+    }
+    if matches.is_present("bytecode") {
+        for (inst, i) in program.iter().zip(0..) {
+            println!("{:<2} - {:?}", i, inst);
+        }
+        return;
+    }
+    let mut ctx = interp::Context::with_args(args);
+    ctx.fs_access = matches.is_present("allow-fs");
+    ctx.strict_args = matches.is_present("strict-args");
+    ctx.int_checks = matches.is_present("int-checks");
+    ctx.trace_calls = matches.is_present("trace");
+    ctx.debug_stepping = matches.is_present("debug");
+    if matches.is_present("deterministic") {
+        ctx.make_deterministic();
+    }
+    if ctx.debug_stepping && ctx.register("__stack", builtins::debug::stack_func).is_err() {
+        eprintln!("ERROR: could not define global variable");
+        std::process::exit(1);
+    }
+    if let Some(limit) = matches.value_of("max-steps") {
+        let limit = match limit.parse::<usize>() {
+            Ok(limit) if limit > 0 => limit,
+            _ => {
+                eprintln!("ERROR: invalid step limit '{}'", limit);
+                std::process::exit(1);
+            }
+        };
+        ctx.set_yield_hook(limit, Box::new(|_| {
+            Err(LangError::Throw(interp::new_value(
+                interp::VarValues::Str(String::from("<max-steps:limit reached>"))
+            )))
+        }));
+    }
+    if let Some(secs) = matches.value_of("timeout") {
+        let secs = match secs.parse::<f64>() {
+            Ok(secs) if secs >= 0.0 && secs.is_finite() => secs,
+            _ => {
+                eprintln!("ERROR: invalid timeout '{}'", secs);
+                std::process::exit(1);
+            }
+        };
+        let token = ctx.cancel_token();
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_secs_f64(secs));
+            token.store(true, std::sync::atomic::Ordering::Relaxed);
+        });
+    }
+    for (name, value) in defines {
+        if ctx.bind(&name, value).is_err() {
+            eprintln!("ERROR: could not define global variable");
+            std::process::exit(1);
+        }
+    }
+    let escape = matches.value_of("escape").map(|name| {
+        match EscapePolicy::from_name(name) {
+            Some(policy) => policy,
+            None => {
+                eprintln!("ERROR: unknown escape policy '{}', expected html, shell or json", name);
+                std::process::exit(1);
+            }
+        }
+    });
+    let ret = match escape {
+        Some(policy) => ctx.interpret(&program, &mut EscapingOutput {
+            inner: &mut StdOutOutput{},
+            policy,
+        }),
+        None => ctx.interpret(&program, &mut StdOutOutput{}),
+    };
+
+    match ret {
+        Ok(_) => {
+            println!();
+            if matches.is_present("test") {
+                run_tests(&mut ctx);
+            }
+        }
+        Err(err @ LangError::Throw(_)) => {
+            // finish off any partial output, and keep the error out of stdout
+            println!();
+            eprintln!("UNCAUGHT ERROR: {}", err);
+            std::process::exit(1);
+        }
+        Err(LangError::CatchUnwind(_)) => {
+            panic!("INTERNAL ERROR: catchunwind escaped interpreter");
+        }
+    }
+}
+// a define with dots in its name, as in server.port=80, sets a key in a map,
+// with the maps along the way made as they're needed
+enum Define {
+    Value(String),
+    Map(BTreeMap<String, Define>),
+}
+
+impl IntoVarValues for Define {
+    fn into_varvalues(self) -> interp::Gc<interp::VarValues> {
+        match self {
+            Define::Value(s) => to_varvalues(s),
+            Define::Map(map) => to_varvalues(map),
+        }
+    }
+}
+
+// a name can't be both a value and a map, so a=1 and a.b=2 conflict
+fn add_define(defines: &mut BTreeMap<String, Define>, name: &str, value: &str) -> Result<(), ()> {
+    match name.find('.') {
+        None => match defines.get(name) {
+            Some(Define::Map(_)) => Err(()),
+            _ => {
+                defines.insert(name.to_owned(), Define::Value(value.to_owned()));
+                Ok(())
+            }
+        },
+        Some(i) => {
+            let entry = defines.entry(name[..i].to_owned())
+                .or_insert_with(|| Define::Map(BTreeMap::new()));
+            match entry {
+                Define::Map(map) => add_define(map, &name[i+1..], value),
+                Define::Value(_) => Err(()),
+            }
+        }
+    }
+}
+
+// calls each test_ function with no args, and counts it as failed if it throws
+fn run_tests(ctx: &mut interp::Context) {
+    let tests = match ctx.functions_named("test_") {
+        Ok(tests) => tests,
+        Err(_) => {
+            eprintln!("ERROR: could not find tests");
+            std::process::exit(1);
+        }
+    };
+    let mut failed = 0;
+    for (name, func) in &tests {
+        match ctx.call_value(func, Vec::new()) {
+            Ok(_) => {
+                println!("test {} ... ok", name);
+            }
+            Err(err) => {
+                println!("test {} ... FAILED: {}", name, err);
+                failed += 1;
+            }
+        }
+    }
+    println!("{} tests: {} passed, {} failed", tests.len(), tests.len() - failed, failed);
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
+
+// the program is either the code given with -c, or the file named by the first arg
+fn read_input(matches: &ArgMatches, args: &mut Vec<&str>) -> String {
     match matches.value_of("code") {
         None => {
             if args.is_empty() {
                 eprintln!("ERROR: no program inputted");
-                return;
+                std::process::exit(1);
             }
             let filename = args.remove(0);
-            input = fs::read_to_string(filename)
-                .expect("ERROR: could not read file");
+            match fs::read_to_string(filename) {
+                Ok(source) => source,
+                Err(_) => {
+                    eprintln!("ERROR: could not read file '{}'", filename);
+                    std::process::exit(1);
+                }
+            }
         }
-        Some(name) => {
-            input = name.to_owned();
+        Some(code) => {
+            code.to_owned()
         }
     }
-    let args = args.into_iter().map(|s| s.to_owned()).collect();
+}
 
-    let ast = match parse::run_parser(&input) {
-        Ok(v) => v,
+fn compile_program(matches: &ArgMatches, input: &str, defined_names: &[String]) -> Vec<Instruction> {
+    // loaded files are compiled along with the program, so that everything
+    // they define is already in the global scope by the time it starts
+    let mut ast = Vec::new();
+    for filename in matches.values_of("load").into_iter().flatten() {
+        let source = match fs::read_to_string(filename) {
+            Ok(source) => source,
+            Err(_) => {
+                eprintln!("ERROR: could not read file '{}'", filename);
+                std::process::exit(1);
+            }
+        };
+        match parse::run_parser(&source) {
+            Ok(v) => ast.extend(v),
+            Err(_) => {
+                eprintln!("ERROR: could not parse file '{}'", filename);
+                std::process::exit(1);
+            }
+        }
+    }
+    match parse::run_parser(input) {
+        Ok(v) => ast.extend(v),
         Err(_) => {
             eprintln!("ERROR: could not parse program");
-            return;
+            std::process::exit(1);
         }
-    };
+    }
     //println!("ast: {:?}", ast);
-    let program = match bytecode::generate_bytecode(&ast) {
+    let program = match bytecode::generate_bytecode(&ast, defined_names) {
         Ok(prog) => prog,
         Err(val) => {
-            eprintln!("SYNTAX ERROR: {:?}", val);
-            return;
+            eprintln!("SYNTAX ERROR: {}", val);
+            std::process::exit(1);
         }
     };
-    // for (inst, i) in program.iter().zip(0..) {
-    //     println!("{:<2} - {:?}", i, inst);
-    // }
-    let mut ctx = interp::Context::with_args(args);
-    let ret = ctx.interpret(&program, &mut StdOutOutput{});
+    if let Err(err) = bytecode::validate_program(&program) {
+        eprintln!("INTERNAL ERROR: invalid bytecode: {}", err);
+        std::process::exit(1);
+    }
+    program
+}
 
-    match ret {
-        Ok(_) => {
-            println!();
-        }
-        Err(LangError::Throw(v)) => {
-            println!("{}", v.borrow().to_string());
-        }
-        Err(LangError::CatchUnwind(_)) => {
-            panic!("INTERNAL ERROR: catchunwind escaped interpreter");
+#[cfg(feature = "bytecode-files")]
+fn load_compiled(matches: &ArgMatches) -> Option<Vec<Instruction>> {
+    let filename = matches.value_of("run-compiled")?;
+    match bytecode_file::load_program(filename) {
+        Ok(program) => Some(program),
+        Err(err) => {
+            eprintln!("ERROR: could not load bytecode from '{}': {}", filename, err);
+            std::process::exit(1);
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(not(feature = "bytecode-files"))]
+fn load_compiled(_matches: &ArgMatches) -> Option<Vec<Instruction>> {
+    None
+}
@@ -0,0 +1,78 @@
+// passes rust data into programs with Context::bind, and reads it back
+// it has no test harness, for the same reason as embed.rs
+mod common;
+
+use std::collections::HashMap;
+use individual_project::lang_core::interp::{Context, Gc, VarValues};
+use individual_project::lang_core::convert::to_varvalues;
+use libgc::GcAllocator;
+use common::run;
+
+#[global_allocator]
+static ALLOCATOR: GcAllocator = GcAllocator;
+
+fn bind_nested_map() {
+    let mut server: HashMap<String, Gc<VarValues>> = HashMap::new();
+    server.insert(String::from("host"), to_varvalues("localhost"));
+    server.insert(String::from("port"), to_varvalues(8080));
+    server.insert(String::from("tags"), to_varvalues(vec!["a", "b"]));
+    let mut config = HashMap::new();
+    config.insert(String::from("server"), to_varvalues(server));
+    config.insert(String::from("debug"), to_varvalues(None::<String>));
+
+    let mut ctx = Context::new();
+    ctx.bind("config", config).unwrap();
+    let code = "{config[server][host]}:{config[server][port]}|{config[server][tags][1]}|[{config[debug]}]";
+    assert_eq!(run(&mut ctx, &["config"], code), Ok(String::from("localhost:8080|b|[]")));
+    // a bound map is a value like any other, so a program can change it
+    let code = "{set:config[server][port]:{add:{config[server][port]}:1;};}{config[server][port]}";
+    assert_eq!(run(&mut ctx, &["config"], code), Ok(String::from("8081")));
+}
+
+#[cfg(feature = "serde-values")]
+fn bind_serialized() {
+    use serde::Serialize;
+    use individual_project::lang_core::serde_values::serialize_varvalues;
+
+    #[derive(Serialize)]
+    enum Role {
+        Admin,
+        Guest { expires: u32 },
+    }
+
+    #[derive(Serialize)]
+    struct User {
+        name: String,
+        age: u8,
+        roles: Vec<Role>,
+        scores: HashMap<u32, f64>,
+        email: Option<String>,
+    }
+
+    let mut scores = HashMap::new();
+    scores.insert(2024, 1.5);
+    let user = User {
+        name: String::from("bob"),
+        age: 30,
+        roles: vec![Role::Admin, Role::Guest { expires: 5 }],
+        scores,
+        email: None,
+    };
+    let mut ctx = Context::new();
+    ctx.bind("user", serialize_varvalues(&user).unwrap()).unwrap();
+    let code = "{user[name]} {user[age]}|{user[roles][0]}|{user[roles][1][Guest][expires]}|{user[scores][2024]}|[{user[email]}]";
+    assert_eq!(run(&mut ctx, &["user"], code), Ok(String::from("bob 30|Admin|5|1.5|[]")));
+
+    // a map key has to be something that prints as a string
+    let mut bad = HashMap::new();
+    bad.insert(vec![1], 1);
+    let err = serialize_varvalues(&bad).unwrap_err();
+    assert_eq!(err.to_string(), "map keys must be strings or numbers");
+}
+
+fn main() {
+    bind_nested_map();
+    #[cfg(feature = "serde-values")]
+    bind_serialized();
+    println!("bind: ok");
+}
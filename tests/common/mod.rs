@@ -0,0 +1,41 @@
+// what the tests that embed the interpreter share
+use individual_project::lang_core::{parse, bytecode};
+use individual_project::lang_core::interp::{
+    Context,
+    Gc,
+    LangResult,
+    LangError,
+    Outputter,
+    VarValues,
+    borrow_val
+};
+
+struct StringOutput {
+    text: String,
+}
+
+impl Outputter for StringOutput {
+    fn output_string(&mut self, s: &str, _: Option<f64>) {
+        self.text.push_str(s);
+    }
+
+    fn output_value(&mut self, v: Gc<VarValues>) -> LangResult<()> {
+        self.text.push_str(&borrow_val(&v)?.to_string());
+        Ok(())
+    }
+}
+
+// whatever the program output, or the text of what it threw
+pub fn run(ctx: &mut Context, predefined: &[&str], code: &str) -> Result<String, String> {
+    let ast = parse::run_parser(code).expect("program should parse");
+    let predefined: Vec<String> = predefined.iter().map(|s| s.to_string()).collect();
+    let program = bytecode::generate_bytecode(&ast, &predefined).expect("program should compile");
+    let mut out = StringOutput {
+        text: String::new(),
+    };
+    match ctx.interpret(&program, &mut out) {
+        Ok(_) => Ok(out.text),
+        Err(LangError::Throw(v)) => Err(borrow_val(&v).unwrap().to_string()),
+        Err(LangError::CatchUnwind(_)) => panic!("catchunwind escaped interpreter"),
+    }
+}
@@ -0,0 +1,96 @@
+// gets rust data back out of values with FromVarValues
+// it has no test harness, for the same reason as embed.rs
+use std::collections::HashMap;
+use std::rc::Rc;
+use individual_project::lang_core::interp::{Gc, VarValues, new_value, borrow_val_mut};
+use individual_project::lang_core::convert::{ConvertErrors, to_varvalues, from_varvalues};
+use libgc::GcAllocator;
+
+#[global_allocator]
+static ALLOCATOR: GcAllocator = GcAllocator;
+
+fn wrong_type<T: std::fmt::Debug>(res: Result<T, ConvertErrors>) -> String {
+    match res {
+        Err(err @ ConvertErrors::WrongType(..)) => err.to_string(),
+        other => panic!("expected a wrong type error, got {:?}", other),
+    }
+}
+
+fn strings() {
+    assert_eq!(from_varvalues::<String>(&to_varvalues("hi")).unwrap(), "hi");
+    assert_eq!(from_varvalues::<String>(&to_varvalues(1.5)).unwrap(), "1.5");
+    assert_eq!(from_varvalues::<String>(&new_value(VarValues::Nil)).unwrap(), "");
+    let ast_str = new_value(VarValues::AstStr(Rc::from("12"), Some(12.0)));
+    assert_eq!(from_varvalues::<String>(&ast_str).unwrap(), "12");
+    assert_eq!(wrong_type(from_varvalues::<String>(&to_varvalues(vec![1]))), "expected string, got list");
+}
+
+fn numbers() {
+    assert_eq!(from_varvalues::<f64>(&to_varvalues(3)).unwrap(), 3.0);
+    assert_eq!(from_varvalues::<f64>(&to_varvalues("2.5")).unwrap(), 2.5);
+    let ast_str = new_value(VarValues::AstStr(Rc::from("7"), Some(7.0)));
+    assert_eq!(from_varvalues::<f64>(&ast_str).unwrap(), 7.0);
+    assert_eq!(wrong_type(from_varvalues::<f64>(&to_varvalues("abc"))), "expected number, got string");
+    assert_eq!(wrong_type(from_varvalues::<f64>(&new_value(VarValues::Nil))), "expected number, got nil");
+}
+
+fn bools() {
+    assert!(from_varvalues::<bool>(&to_varvalues(1)).unwrap());
+    assert!(!from_varvalues::<bool>(&to_varvalues(0)).unwrap());
+    assert!(!from_varvalues::<bool>(&to_varvalues("")).unwrap());
+    assert!(from_varvalues::<bool>(&to_varvalues(vec![1])).unwrap());
+}
+
+fn lists() {
+    let val = to_varvalues(vec![vec!["a", "b"], vec![]]);
+    let expected: Vec<Vec<String>> = vec![vec![String::from("a"), String::from("b")], vec![]];
+    assert_eq!(from_varvalues::<Vec<Vec<String>>>(&val).unwrap(), expected);
+    assert_eq!(from_varvalues::<Vec<f64>>(&to_varvalues(vec![1, 2])).unwrap(), vec![1.0, 2.0]);
+    assert_eq!(wrong_type(from_varvalues::<Vec<f64>>(&to_varvalues("1,2"))), "expected list, got string");
+    // an item of the wrong type fails the whole list
+    let mixed = to_varvalues(vec![to_varvalues(1), to_varvalues(vec![2])]);
+    assert_eq!(wrong_type(from_varvalues::<Vec<f64>>(&mixed)), "expected number, got list");
+}
+
+fn maps() {
+    let mut map = HashMap::new();
+    map.insert(String::from("a"), to_varvalues(1));
+    map.insert(String::from("b"), to_varvalues(2));
+    let val = to_varvalues(map);
+    let nums = from_varvalues::<HashMap<String, f64>>(&val).unwrap();
+    assert_eq!(nums.len(), 2);
+    assert_eq!(nums["a"], 1.0);
+    assert_eq!(nums["b"], 2.0);
+    assert_eq!(wrong_type(from_varvalues::<HashMap<String, f64>>(&to_varvalues(vec![1]))), "expected map, got list");
+    assert_eq!(wrong_type(from_varvalues::<HashMap<String, Vec<f64>>>(&val)), "expected list, got number");
+}
+
+fn other_types() {
+    let func: Gc<VarValues> = new_value(VarValues::RustFunc(|ctx, _| Ok(ctx.new_nil())));
+    assert_eq!(wrong_type(from_varvalues::<String>(&func)), "expected string, got function");
+    let set = new_value(VarValues::Set(Default::default()));
+    assert_eq!(wrong_type(from_varvalues::<Vec<String>>(&set)), "expected list, got set");
+    let range = new_value(VarValues::Range(0.0, 1.0, 1.0));
+    assert_eq!(wrong_type(from_varvalues::<HashMap<String, f64>>(&range)), "expected map, got range");
+}
+
+fn cannot_borrow() {
+    // a value being changed can't be read at the same time
+    let val = to_varvalues(vec![1]);
+    let _guard = borrow_val_mut(&val).unwrap();
+    match from_varvalues::<Vec<f64>>(&val) {
+        Err(err @ ConvertErrors::CannotBorrow) => assert_eq!(err.to_string(), "value is in use"),
+        other => panic!("expected a borrow error, got {:?}", other),
+    }
+}
+
+fn main() {
+    strings();
+    numbers();
+    bools();
+    lists();
+    maps();
+    other_types();
+    cannot_borrow();
+    println!("convert: ok");
+}
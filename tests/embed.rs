@@ -0,0 +1,73 @@
+// runs programs through the library, the way a program embedding the
+// interpreter would, rather than through the binary like lang_tests
+// it has no test harness, as values are only kept alive by the collector
+// while they're on the main thread's stack
+mod common;
+
+use individual_project::lang_core::interp::{
+    Context,
+    Gc,
+    LangResult,
+    LangError,
+    VarValues,
+    borrow_val,
+    new_value
+};
+use libgc::GcAllocator;
+use common::run;
+
+#[global_allocator]
+static ALLOCATOR: GcAllocator = GcAllocator;
+
+fn double_func(ctx: &mut Context, args: Vec<Gc<VarValues>>) -> LangResult<Gc<VarValues>> {
+    if args.len() != 1 {
+        let msg = format!("<double:expected 1 arg, got {}>", args.len());
+        return Err(LangError::Throw(new_value(VarValues::Str(msg))));
+    }
+    let n = match &*borrow_val(&args[0])? {
+        VarValues::Num(n) |
+        VarValues::AstStr(_, Some(n)) => *n,
+        _ => {
+            return Err(LangError::Throw(new_value(VarValues::Str(String::from("<double:invalid num>")))));
+        },
+    };
+    Ok(ctx.new_num(n * 2.0))
+}
+
+fn register_fn() {
+    let mut ctx = Context::new();
+    ctx.register("double", double_func).unwrap();
+    assert_eq!(run(&mut ctx, &["double"], "{double:21;}|{double:{double:1.5;};}"), Ok(String::from("42|6")));
+    assert_eq!(run(&mut ctx, &["double"], "{double:a;}"), Err(String::from("<double:invalid num>")));
+}
+
+fn register_closure() {
+    let mut ctx = Context::new();
+    let factor = 2.0;
+    ctx.register_closure("double", move |ctx, args| {
+        let n = match args.first() {
+            Some(arg) => match &*borrow_val(arg)? {
+                VarValues::Num(n) |
+                VarValues::AstStr(_, Some(n)) => *n,
+                _ => 0.0,
+            },
+            None => 0.0,
+        };
+        Ok(ctx.new_num(n * factor))
+    }).unwrap();
+    assert_eq!(run(&mut ctx, &["double"], "{double:21;}|{foreach:n:{list:1:2;}:{double:{n};},;}"), Ok(String::from("42|2,4,")));
+}
+
+fn register_shadows_builtin() {
+    // a registered builtin replaces one of the same name, like a set would
+    let mut ctx = Context::new();
+    ctx.register("add", double_func).unwrap();
+    assert_eq!(run(&mut ctx, &["add"], "{add:4;}"), Ok(String::from("8")));
+}
+
+fn main() {
+    register_fn();
+    register_closure();
+    register_shadows_builtin();
+    println!("embed: ok");
+}